@@ -1,36 +1,40 @@
 //! Burst data types passed between L1 and L2.
 
+#[derive(Default)]
 #[repr(C)]
 pub struct RxBurstInfo {
-    timestamp: i64,
-    /// Received signal strength (unit TBD)
-    rssi: f32,
-    /// Esimated carrier frequency offset in Hz
-    cfo: f32,
+    /// Timestamp of the first symbol of the burst in nanoseconds,
+    /// referenced to the antenna, i.e. receiver delays compensated.
+    pub timestamp: i64,
+    /// Received signal strength in dBm if RSSI calibration offset
+    /// has been configured, otherwise in dBFS.
+    pub rssi: f32,
+    /// Estimated carrier frequency offset in Hz
+    pub cfo: f32,
 }
 
 #[repr(C)]
 pub struct RxDlBurst {
-    info: RxBurstInfo,
-    bits: [u8; 510],
+    pub info: RxBurstInfo,
+    pub bits: [u8; 510],
 }
 
 #[repr(C)]
 pub struct RxUlNormalBurst {
-    info: RxBurstInfo,
-    bits: [u8; 462],
+    pub info: RxBurstInfo,
+    pub bits: [u8; 462],
 }
 
 #[repr(C)]
 pub struct RxUlControlBurst {
-    info: RxBurstInfo,
-    bits: [u8; 206],
+    pub info: RxBurstInfo,
+    pub bits: [u8; 206],
 }
 
 #[repr(C)]
 pub struct RxDmoBurst {
-    info: RxBurstInfo,
-    bits: [u8; 470],
+    pub info: RxBurstInfo,
+    pub bits: [u8; 470],
 }
 
 #[repr(C)]
@@ -76,6 +80,17 @@ pub enum RxBurst {
     DmoSync(RxDmoBurst),
 }
 
+// Bursts are passed to and from C by value as tagged unions,
+// so the large variant cannot be boxed.
+#[allow(clippy::large_enum_variant)]
+#[repr(C)]
+pub enum TxSubslotBurst {
+    /// No burst to transmit in subslot.
+    None,
+    /// Control up-link burst in subslot.
+    UlControl([u8; 206]),
+}
+
 #[repr(C)]
 pub enum TxBurst {
     /// No burst to transmit.
@@ -90,4 +105,11 @@ pub enum TxBurst {
     /// burst (since they have the same number of symbols),
     /// so both use the same value.
     Dmo([u8; 470]),
+    /// Normal up-link burst.
+    /// Modulator does not care which training sequence is used,
+    /// so both use the same value.
+    UlNormal([u8; 462]),
+    /// Control up-link burst(s) in subslots.
+    /// Each subslot may contain a control up-link burst or no burst.
+    UlControl([TxSubslotBurst; 2]),
 }
@@ -0,0 +1,633 @@
+//! L1 configuration.
+//!
+//! Configuration can be read from an INI-style file such as:
+//!
+//! ```text
+//! [radio]
+//! # Radio backend: soapy, file, sigmf, loopback, channel or net
+//! backend = soapy
+//! fs = 1.8e6
+//! # Processing block length in samples. Default is about 4 ms.
+//! blocklen = 7200
+//! # Center frequencies
+//! rx_freq = 424e6
+//! tx_freq = 434e6
+//! # Receive frequency is transmit frequency minus duplex spacing
+//! duplex_spacing = 10e6
+//!
+//! [soapy]
+//! rx_ant = LNAL
+//! tx_ant = BAND1
+//! # Overall gain or a list of name:value pairs for gain elements
+//! rx_gain = 50
+//! tx_gain = PAD:52, IAMP:0
+//! dev_args = driver=lime
+//!
+//! # Each [carrier] section adds a carrier.
+//! [carrier]
+//! # Transmit frequency, or receive frequency for RX-only carriers
+//! freq = 434.025e6
+//! # rx, tx or both
+//! direction = both
+//! # main_control, traffic, dmo or ms (mobile station)
+//! role = main_control
+//! ```
+//!
+//! Keys not given in the file keep their default values.
+
+use crate::dsp;
+use crate::io;
+use crate::L1Error;
+
+pub struct SoapyConfig {
+    pub latency_blocks: usize,
+    pub rx_chan:  usize,
+    pub tx_chan:  usize,
+    pub rx_ant:   String,
+    pub tx_ant:   String,
+    pub rx_gain:  Vec<(Option<String>, f64)>,
+    pub tx_gain:  Vec<(Option<String>, f64)>,
+    pub dev_args: Vec<(String, String)>,
+    pub rx_args:  Vec<(String, String)>,
+    pub tx_args:  Vec<(String, String)>,
+}
+
+pub struct FileConfig {
+    pub stop_time: i64,
+    pub rx_filename: Option<String>,
+    pub rx_format: io::file::SampleFormat,
+    pub tx_filename: Option<String>,
+    pub tx_format: io::file::SampleFormat,
+}
+
+pub struct SigmfConfig {
+    pub stop_time: i64,
+    pub rx_name: Option<String>,
+    pub tx_name: Option<String>,
+}
+
+pub struct LoopbackConfig {
+    pub latency_blocks: usize,
+    pub stop_time: i64,
+}
+
+pub struct ChannelConfig {
+    pub latency_blocks: usize,
+    pub stop_time: i64,
+    pub impairments: io::channel::Impairments,
+}
+
+pub struct NetConfig {
+    pub latency_blocks: usize,
+    pub transport: io::net::Transport,
+    pub address: String,
+    pub local_address: String,
+}
+
+pub enum Backend {
+    Soapy,
+    File,
+    Sigmf,
+    Loopback,
+    Channel,
+    Net,
+}
+
+/// Which directions a carrier is used for.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+#[repr(C)]
+pub enum CarrierDirection {
+    /// Receive only
+    Rx,
+    /// Transmit only
+    Tx,
+    /// Both receive and transmit.
+    /// Receive frequency is separated by duplex spacing,
+    /// except for direct mode carriers.
+    Both,
+}
+
+/// What a carrier is used for.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+#[repr(C)]
+pub enum CarrierRole {
+    /// Main control channel of a cell
+    MainControl,
+    /// Traffic carrier of a cell
+    Traffic,
+    /// Direct mode channel
+    Dmo,
+    /// Mobile station or test terminal,
+    /// receiving downlink bursts from a base station
+    /// and transmitting uplink bursts.
+    MobileStation,
+}
+
+#[derive(Copy, Clone, Debug)]
+#[repr(C)]
+pub struct CarrierConfig {
+    /// Carrier frequency in Hz.
+    /// This is the transmit frequency for carriers used for transmitting
+    /// and receive frequency for receive only carriers.
+    pub freq: f64,
+    pub direction: CarrierDirection,
+    pub role: CarrierRole,
+}
+
+pub struct L1Config {
+    /// Which radio backend to use
+    pub backend: Backend,
+    /// Sample rate
+    pub fs: f64,
+    /// Processing block length in samples.
+    /// If None, a block length of 4 ms is used.
+    pub blocklen: Option<usize>,
+    /// Receive center frequency
+    pub rx_freq: f64,
+    /// Transmit center frequency
+    pub tx_freq: f64,
+    /// Difference between transmit and receive frequencies of carriers
+    pub duplex_spacing: f64,
+    /// Offset added to received signal strength to convert
+    /// from dBFS to dBm. Zero reports RSSI in dBFS.
+    pub rssi_offset: f64,
+    /// Configuration for SoapySDR backend
+    pub soapy: SoapyConfig,
+    /// Configuration for file backend
+    pub file: FileConfig,
+    /// Configuration for SigMF backend
+    pub sigmf: SigmfConfig,
+    /// Configuration for loopback backend
+    pub loopback: LoopbackConfig,
+    /// Configuration for channel simulator backend
+    pub channel: ChannelConfig,
+    /// Configuration for network backend
+    pub net: NetConfig,
+    /// Carriers
+    pub carriers: Vec<CarrierConfig>,
+}
+
+impl Default for L1Config {
+    fn default() -> Self {
+        Self {
+            backend: Backend::Soapy,
+            fs: 1.8e6,
+            blocklen: None,
+            rx_freq: 434e6,
+            tx_freq: 434e6,
+            duplex_spacing: 0.0,
+            rssi_offset: 0.0,
+            soapy: SoapyConfig {
+                latency_blocks: 3,
+                rx_chan: 0,
+                tx_chan: 0,
+                rx_ant: "LNAL".to_string(),
+                tx_ant: "BAND1".to_string(),
+                rx_gain: vec![(None, 50.0)],
+                tx_gain: vec![
+                    (Some("PAD" .to_string()), 52.0),
+                    (Some("IAMP".to_string()), 0.0),
+                ],
+                dev_args: vec![("driver".to_string(), "lime".to_string())],
+                rx_args: vec![],
+                tx_args: vec![],
+            },
+            file: FileConfig {
+                stop_time: 1e9 as i64,
+                rx_filename: None,
+                rx_format: io::file::SampleFormat::Cf32,
+                tx_filename: Some("test_out.raw".to_string()),
+                tx_format: io::file::SampleFormat::Cf32,
+            },
+            sigmf: SigmfConfig {
+                stop_time: i64::MAX,
+                rx_name: None,
+                tx_name: None,
+            },
+            loopback: LoopbackConfig {
+                latency_blocks: 3,
+                stop_time: i64::MAX,
+            },
+            channel: ChannelConfig {
+                latency_blocks: 3,
+                stop_time: i64::MAX,
+                impairments: io::channel::Impairments::default(),
+            },
+            net: NetConfig {
+                latency_blocks: 3,
+                transport: io::net::Transport::Tcp,
+                address: "127.0.0.1:4000".to_string(),
+                local_address: "0.0.0.0:4001".to_string(),
+            },
+            carriers: vec![
+                CarrierConfig {
+                    freq: 434.025e6,
+                    direction: CarrierDirection::Both,
+                    role: CarrierRole::MainControl,
+                },
+                CarrierConfig {
+                    freq: 434.05e6,
+                    direction: CarrierDirection::Both,
+                    role: CarrierRole::Traffic,
+                },
+            ],
+        }
+    }
+}
+
+impl L1Config {
+    /// Read configuration from a file.
+    pub fn from_file(path: &str) -> Result<Self, String> {
+        match std::fs::read_to_string(path) {
+            Ok(text) => Self::parse(&text).map_err(|err| format!("{}: {}", path, err)),
+            Err(err) => Err(format!("Failed to read {}: {}", path, err)),
+        }
+    }
+
+    /// Parse configuration from a string.
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let mut conf = Self::default();
+        let mut section = String::new();
+        let mut carriers_given = false;
+        for (i, line) in text.lines().enumerate() {
+            let line = line.split(['#', ';']).next().unwrap().trim();
+            if line.is_empty() {
+                continue;
+            }
+            conf.parse_line(line, &mut section, &mut carriers_given)
+                .map_err(|err| format!("line {}: {}", i + 1, err))?;
+        }
+        conf.validate()?;
+        Ok(conf)
+    }
+
+    fn parse_line(
+        &mut self,
+        line: &str,
+        section: &mut String,
+        carriers_given: &mut bool,
+    ) -> Result<(), String> {
+        if let Some(name) = line.strip_prefix('[').and_then(|l| l.strip_suffix(']')) {
+            *section = name.trim().to_string();
+            match section.as_str() {
+                "radio" | "soapy" | "file" | "sigmf" | "loopback" | "channel" | "net" => {},
+                "carrier" => {
+                    // Carriers given in a file replace the default ones.
+                    if !*carriers_given {
+                        self.carriers.clear();
+                        *carriers_given = true;
+                    }
+                    self.carriers.push(CarrierConfig {
+                        freq: 0.0,
+                        direction: CarrierDirection::Both,
+                        role: CarrierRole::Traffic,
+                    });
+                },
+                _ => return Err(format!("unknown section [{}]", section)),
+            }
+            return Ok(());
+        }
+
+        let (key, value) = match line.split_once('=') {
+            Some((key, value)) => (key.trim(), value.trim()),
+            None => return Err(format!("expected 'key = value' or '[section]', found '{}'", line)),
+        };
+        match (section.as_str(), key) {
+            ("radio", "backend") => self.backend = match value {
+                "soapy" => Backend::Soapy,
+                "file"  => Backend::File,
+                "sigmf" => Backend::Sigmf,
+                "loopback" => Backend::Loopback,
+                "channel" => Backend::Channel,
+                "net"   => Backend::Net,
+                _ => return Err(format!("unknown backend '{}', expected soapy, file, sigmf, loopback, channel or net", value)),
+            },
+            ("radio", "fs")       => self.fs = parse_number(key, value)?,
+            ("radio", "blocklen") => self.blocklen = Some(parse_number(key, value)?),
+            ("radio", "rx_freq")  => self.rx_freq = parse_number(key, value)?,
+            ("radio", "tx_freq")  => self.tx_freq = parse_number(key, value)?,
+            ("radio", "duplex_spacing") => self.duplex_spacing = parse_number(key, value)?,
+            ("radio", "rssi_offset") => self.rssi_offset = parse_number(key, value)?,
+
+            ("soapy", "latency_blocks") => self.soapy.latency_blocks = parse_number(key, value)?,
+            ("soapy", "rx_chan")  => self.soapy.rx_chan = parse_number(key, value)?,
+            ("soapy", "tx_chan")  => self.soapy.tx_chan = parse_number(key, value)?,
+            ("soapy", "rx_ant")   => self.soapy.rx_ant = value.to_string(),
+            ("soapy", "tx_ant")   => self.soapy.tx_ant = value.to_string(),
+            ("soapy", "rx_gain")  => self.soapy.rx_gain = parse_gains(key, value)?,
+            ("soapy", "tx_gain")  => self.soapy.tx_gain = parse_gains(key, value)?,
+            ("soapy", "dev_args") => self.soapy.dev_args = parse_args(key, value)?,
+            ("soapy", "rx_args")  => self.soapy.rx_args = parse_args(key, value)?,
+            ("soapy", "tx_args")  => self.soapy.tx_args = parse_args(key, value)?,
+
+            ("file", "stop_time") => self.file.stop_time = parse_number::<f64>(key, value)? as i64,
+            ("file", "rx_filename") => self.file.rx_filename = parse_filename(value),
+            ("file", "rx_format")   => self.file.rx_format = parse_format(value)?,
+            ("file", "tx_filename") => self.file.tx_filename = parse_filename(value),
+            ("file", "tx_format")   => self.file.tx_format = parse_format(value)?,
+
+            ("sigmf", "stop_time") => self.sigmf.stop_time = parse_number::<f64>(key, value)? as i64,
+            ("sigmf", "rx_name") => self.sigmf.rx_name = parse_filename(value),
+            ("sigmf", "tx_name") => self.sigmf.tx_name = parse_filename(value),
+
+            ("loopback", "latency_blocks") => self.loopback.latency_blocks = parse_number(key, value)?,
+            ("loopback", "stop_time") => self.loopback.stop_time = parse_number::<f64>(key, value)? as i64,
+
+            ("channel", "latency_blocks") => self.channel.latency_blocks = parse_number(key, value)?,
+            ("channel", "stop_time")    => self.channel.stop_time = parse_number::<f64>(key, value)? as i64,
+            ("channel", "gain")         => self.channel.impairments.gain = parse_number(key, value)?,
+            ("channel", "noise")        => self.channel.impairments.noise = parse_number(key, value)?,
+            ("channel", "freq_offset")  => self.channel.impairments.freq_offset = parse_number(key, value)?,
+            ("channel", "drift")        => self.channel.impairments.drift = parse_number(key, value)?,
+            ("channel", "fading")       => {
+                let (fading, speed) = parse_fading(value)?;
+                self.channel.impairments.fading = fading;
+                self.channel.impairments.speed = speed;
+            },
+            ("channel", "iq_amplitude") => self.channel.impairments.iq_amplitude = parse_number(key, value)?,
+            ("channel", "iq_phase")     => self.channel.impairments.iq_phase = parse_number(key, value)?,
+            ("channel", "seed")         => self.channel.impairments.seed = parse_number(key, value)?,
+
+            ("net", "latency_blocks") => self.net.latency_blocks = parse_number(key, value)?,
+            ("net", "transport") => self.net.transport = match value {
+                "tcp" => io::net::Transport::Tcp,
+                "udp" => io::net::Transport::Udp,
+                _ => return Err(format!("unknown transport '{}', expected tcp or udp", value)),
+            },
+            ("net", "address") => self.net.address = value.to_string(),
+            ("net", "local_address") => self.net.local_address = value.to_string(),
+
+            ("carrier", "freq") => self.carriers.last_mut().unwrap().freq = parse_number(key, value)?,
+            ("carrier", "direction") => self.carriers.last_mut().unwrap().direction = match value {
+                "rx"   => CarrierDirection::Rx,
+                "tx"   => CarrierDirection::Tx,
+                "both" => CarrierDirection::Both,
+                _ => return Err(format!("unknown direction '{}', expected rx, tx or both", value)),
+            },
+            ("carrier", "role") => self.carriers.last_mut().unwrap().role = match value {
+                "main_control" => CarrierRole::MainControl,
+                "traffic"      => CarrierRole::Traffic,
+                "dmo"          => CarrierRole::Dmo,
+                "ms"           => CarrierRole::MobileStation,
+                _ => return Err(format!("unknown role '{}', expected main_control, traffic, dmo or ms", value)),
+            },
+
+            ("", _) => return Err(format!("key '{}' given before any section", key)),
+            _ => return Err(format!("unknown key '{}' in section [{}]", key, section)),
+        }
+        Ok(())
+    }
+
+    /// Check that configuration values make sense.
+    fn validate(&self) -> Result<(), String> {
+        if !self.fs.is_finite() || self.fs <= 0.0 {
+            return Err(format!("sample rate fs shall be positive and finite, got {}", self.fs));
+        }
+        if self.blocklen == Some(0) {
+            return Err("blocklen shall be positive".to_string());
+        }
+        if let Backend::Soapy = self.backend {
+            if self.soapy.latency_blocks < 2 {
+                return Err(format!("latency_blocks shall be at least 2, got {}", self.soapy.latency_blocks));
+            }
+        }
+        if let Backend::Loopback = self.backend {
+            if self.loopback.latency_blocks < 1 {
+                return Err("loopback latency_blocks shall be at least 1".to_string());
+            }
+        }
+        if let Backend::Channel = self.backend {
+            if self.channel.latency_blocks < 1 {
+                return Err("channel latency_blocks shall be at least 1".to_string());
+            }
+        }
+        if let Backend::Net = self.backend {
+            if self.net.latency_blocks < 1 {
+                return Err("net latency_blocks shall be at least 1".to_string());
+            }
+        }
+        if self.carriers.is_empty() {
+            return Err("no carriers configured".to_string());
+        }
+        if self.carriers.iter().any(|carrier| carrier.freq <= 0.0) {
+            return Err("carrier frequency not given".to_string());
+        }
+        // Carrier frequencies are checked by L1Dsp
+        // since they depend on signal processing parameters.
+        Ok(())
+    }
+
+    /// Processing block length in samples.
+    pub fn blocklen(&self) -> usize {
+        // About 4 ms block length by default,
+        // rounded to a whole number of CIC blocks.
+        self.blocklen.unwrap_or_else(|| {
+            let cic_factor = dsp::cic_factor(self.fs);
+            ((self.fs * 0.004 / cic_factor as f64).round() as usize).max(1) * cic_factor
+        })
+    }
+
+    /// Open radio I/O according to the configuration.
+    pub fn open_radio(&self) -> Result<io::RadioIo, L1Error> {
+        let blocklen = self.blocklen();
+        match self.backend {
+            Backend::File => io::RadioIo::new(&io::RadioIoConfig::File(&io::file::FileIoConfig {
+                blocklen: blocklen,
+                fs: self.fs,
+                stop_time: self.file.stop_time,
+                rx_filename: self.file.rx_filename.as_deref(),
+                rx_format: self.file.rx_format,
+                tx_filename: self.file.tx_filename.as_deref(),
+                tx_format: self.file.tx_format,
+            })),
+            Backend::Sigmf => io::RadioIo::new(&io::RadioIoConfig::Sigmf(&io::sigmf::SigmfIoConfig {
+                blocklen: blocklen,
+                fs: self.fs,
+                rx_freq: self.rx_freq,
+                tx_freq: self.tx_freq,
+                stop_time: self.sigmf.stop_time,
+                rx_name: self.sigmf.rx_name.as_deref(),
+                tx_name: self.sigmf.tx_name.as_deref(),
+            })),
+            Backend::Loopback => io::RadioIo::new(&io::RadioIoConfig::Loopback(&io::loopback::LoopbackIoConfig {
+                blocklen: blocklen,
+                latency_blocks: self.loopback.latency_blocks,
+                fs: self.fs,
+                stop_time: self.loopback.stop_time,
+            })),
+            Backend::Channel => io::RadioIo::new(&io::RadioIoConfig::Channel(&io::channel::ChannelIoConfig {
+                blocklen: blocklen,
+                latency_blocks: self.channel.latency_blocks,
+                fs: self.fs,
+                freq: self.rx_freq,
+                stop_time: self.channel.stop_time,
+                impairments: self.channel.impairments,
+            })),
+            Backend::Net => io::RadioIo::new(&io::RadioIoConfig::Net(&io::net::NetIoConfig {
+                blocklen: blocklen,
+                latency_blocks: self.net.latency_blocks,
+                fs: self.fs,
+                transport: self.net.transport,
+                address: &self.net.address,
+                local_address: &self.net.local_address,
+            })),
+            Backend::Soapy => {
+                let c = &self.soapy;
+                io::RadioIo::new(&io::RadioIoConfig::Soapy(&io::soapy::SoapyIoConfig {
+                    blocklen: blocklen,
+                    latency_blocks: c.latency_blocks,
+                    fs: self.fs,
+                    rx_freq: self.rx_freq,
+                    tx_freq: self.tx_freq,
+                    rx_chan: c.rx_chan,
+                    tx_chan: c.tx_chan,
+                    rx_ant:  &c.rx_ant,
+                    tx_ant:  &c.tx_ant,
+                    rx_gain: &borrow_gains(&c.rx_gain),
+                    tx_gain: &borrow_gains(&c.tx_gain),
+                    dev_args: &borrow_args(&c.dev_args),
+                    rx_args: &borrow_args(&c.rx_args),
+                    tx_args: &borrow_args(&c.tx_args),
+                }))
+            },
+        }
+    }
+}
+
+fn parse_number<T: std::str::FromStr>(key: &str, value: &str) -> Result<T, String> {
+    value.parse::<T>().map_err(|_| format!("invalid value '{}' for {}, expected a number", value, key))
+}
+
+/// Parse a file name. An empty value means no file.
+fn parse_filename(value: &str) -> Option<String> {
+    if value.is_empty() { None } else { Some(value.to_string()) }
+}
+
+/// Parse an I/Q file sample format.
+fn parse_format(value: &str) -> Result<io::file::SampleFormat, String> {
+    match value {
+        "cf32" => Ok(io::file::SampleFormat::Cf32),
+        "cs16" => Ok(io::file::SampleFormat::Cs16),
+        "cu8"  => Ok(io::file::SampleFormat::Cu8),
+        _ => Err(format!("unknown sample format '{}', expected cf32, cs16 or cu8", value)),
+    }
+}
+
+/// Parse a fading profile such as tu50,
+/// consisting of a propagation model and speed in km/h.
+fn parse_fading(value: &str) -> Result<(io::channel::FadingProfile, f64), String> {
+    use io::channel::FadingProfile;
+    if value == "none" {
+        return Ok((FadingProfile::None, 0.0));
+    }
+    let split = value.find(|c: char| c.is_ascii_digit()).unwrap_or(value.len());
+    let profile = match &value[..split] {
+        "tu" => FadingProfile::TypicalUrban,
+        "bu" => FadingProfile::BadUrban,
+        "ht" => FadingProfile::HillyTerrain,
+        _ => return Err(format!("unknown fading profile '{}', expected none, tu, bu or ht followed by speed", value)),
+    };
+    Ok((profile, parse_number("fading speed", &value[split..])?))
+}
+
+/// Parse a gain setting, which is either a single number
+/// for overall gain or a comma separated list of name:value pairs.
+fn parse_gains(key: &str, value: &str) -> Result<Vec<(Option<String>, f64)>, String> {
+    value.split(',').map(|gain| {
+        match gain.split_once(':') {
+            Some((name, v)) => Ok((Some(name.trim().to_string()), parse_number(key, v.trim())?)),
+            None => Ok((None, parse_number(key, gain.trim())?)),
+        }
+    }).collect()
+}
+
+/// Parse a comma separated list of key=value pairs.
+fn parse_args(key: &str, value: &str) -> Result<Vec<(String, String)>, String> {
+    value.split(',').filter(|arg| !arg.trim().is_empty()).map(|arg| {
+        match arg.split_once('=') {
+            Some((k, v)) => Ok((k.trim().to_string(), v.trim().to_string())),
+            None => Err(format!("invalid value '{}' for {}, expected key=value pairs", arg.trim(), key)),
+        }
+    }).collect()
+}
+
+fn borrow_gains(gains: &[(Option<String>, f64)]) -> Vec<(Option<&str>, f64)> {
+    gains.iter().map(|(name, value)| (name.as_deref(), *value)).collect()
+}
+
+fn borrow_args(args: &[(String, String)]) -> Vec<(&str, &str)> {
+    args.iter().map(|(k, v)| (k.as_str(), v.as_str())).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_config() {
+        let conf = L1Config::parse("
+            [radio]
+            backend = file  # comment
+            fs = 2.4e6
+            rx_freq = 390e6
+            tx_freq = 400e6
+            duplex_spacing = 10e6
+            rssi_offset = -105.5
+            [soapy]
+            tx_gain = PAD:40, IAMP:3
+            dev_args = driver=lime, serial=123
+            [file]
+            rx_filename = recording.cu8
+            rx_format = cu8
+            tx_filename =
+            [channel]
+            fading = ht200
+            noise = -50
+            [carrier]
+            freq = 399.9875e6
+            role = main_control
+            [carrier]
+            freq = 400.1e6
+            direction = rx
+            [carrier]
+            freq = 390.025e6
+            role = ms
+        ").unwrap();
+        assert!(matches!(conf.backend, Backend::File));
+        assert_eq!(conf.fs, 2.4e6);
+        assert_eq!(conf.blocklen(), 9603);
+        assert_eq!(conf.soapy.tx_gain, vec![(Some("PAD".to_string()), 40.0), (Some("IAMP".to_string()), 3.0)]);
+        assert_eq!(conf.soapy.dev_args[1], ("serial".to_string(), "123".to_string()));
+        assert_eq!(conf.file.rx_filename.as_deref(), Some("recording.cu8"));
+        assert_eq!(conf.file.rx_format, io::file::SampleFormat::Cu8);
+        assert_eq!(conf.file.tx_filename, None);
+        assert_eq!(conf.file.tx_format, io::file::SampleFormat::Cf32);
+        assert_eq!(conf.duplex_spacing, 10e6);
+        assert_eq!(conf.rssi_offset, -105.5);
+        assert_eq!(conf.channel.impairments.fading, io::channel::FadingProfile::HillyTerrain);
+        assert_eq!(conf.channel.impairments.speed, 200.0);
+        assert_eq!(conf.channel.impairments.noise, -50.0);
+        assert_eq!(conf.carriers.len(), 3);
+        assert_eq!(conf.carriers[0].freq, 399.9875e6);
+        assert_eq!(conf.carriers[0].direction, CarrierDirection::Both);
+        assert_eq!(conf.carriers[0].role, CarrierRole::MainControl);
+        assert_eq!(conf.carriers[1].freq, 400.1e6);
+        assert_eq!(conf.carriers[1].direction, CarrierDirection::Rx);
+        assert_eq!(conf.carriers[1].role, CarrierRole::Traffic);
+        assert_eq!(conf.carriers[2].role, CarrierRole::MobileStation);
+    }
+
+    #[test]
+    fn test_config_errors() {
+        assert_eq!(L1Config::parse("[radio]\nfs = fast").err().unwrap(),
+            "line 2: invalid value 'fast' for fs, expected a number");
+        assert_eq!(L1Config::parse("[radio]\nfoo = 1").err().unwrap(),
+            "line 2: unknown key 'foo' in section [radio]");
+        assert_eq!(L1Config::parse("[carrier]\nrole = bts").err().unwrap(),
+            "line 2: unknown role 'bts', expected main_control, traffic, dmo or ms");
+        assert_eq!(L1Config::parse("[channel]\nfading = ra50").err().unwrap(),
+            "line 2: unknown fading profile 'ra50', expected none, tu, bu or ht followed by speed");
+        assert_eq!(L1Config::parse("[carrier]\nrole = dmo").err().unwrap(),
+            "carrier frequency not given");
+    }
+}
@@ -32,9 +32,41 @@ pub fn make_sinetable(length: usize) -> SineTableType {
     }).collect()
 }
 
+/// Maximum length of a sine table made by make_sinetable_freq.
+pub const MAX_SINETABLE_LEN: usize = 1 << 16;
+
 /// Make a sine table for a given channel spacing and sample rate.
-pub fn make_sinetable_freq(fs: f64, channel_spacing: f64) -> SineTableType {
-    make_sinetable((fs / channel_spacing).round() as usize)
+/// Returns the table and the number of table bins per channel spacing.
+/// Table length is chosen so that both the sample rate and channel spacing
+/// are an integer number of bins, so that every channel can be mixed
+/// to zero frequency exactly.
+/// Returns an error if the frequencies are not integer Hz or
+/// the table would be longer than MAX_SINETABLE_LEN.
+pub fn make_sinetable_freq(fs: f64, channel_spacing: f64) -> Result<(SineTableType, isize), String> {
+    for (name, freq) in [("sample rate", fs), ("channel spacing", channel_spacing)] {
+        if !freq.is_finite() || freq < 1.0 || (freq - freq.round()).abs() > 1e-6 {
+            return Err(format!("{} {} Hz is not a positive integer number of Hz", name, freq));
+        }
+    }
+    let fs = fs.round() as u64;
+    let channel_spacing = channel_spacing.round() as u64;
+    let bin = gcd(fs, channel_spacing);
+    let length = fs / bin;
+    if length > MAX_SINETABLE_LEN as u64 {
+        return Err(format!(
+            "sample rate {} Hz and channel spacing {} Hz would need a sine table of {} entries \
+            but at most {} are supported; use a sample rate with a larger common divisor with channel spacing",
+            fs, channel_spacing, length, MAX_SINETABLE_LEN));
+    }
+    Ok((make_sinetable(length as usize), (channel_spacing / bin) as isize))
+}
+
+/// Greatest common divisor.
+fn gcd(mut a: u64, mut b: u64) -> u64 {
+    while b != 0 {
+        (a, b) = (b, a % b);
+    }
+    a
 }
 
 /// Convert buffer type to integrator type.
@@ -162,6 +194,14 @@ impl<const N: usize> CicDdc<N> {
         int_to_buf(output)
     }
 
+    /// Advance mixer phase as if a number of input samples had been
+    /// processed, so that the phase stays continuous across
+    /// a discontinuity in input signal.
+    pub fn advance(&mut self, samples: i64) {
+        let len = self.sinetable.len() as i64;
+        self.phase = (self.phase as i64 + (samples.rem_euclid(len) * self.freq as i64)).rem_euclid(len) as usize;
+    }
+
     /// Compute scaling factors for a given decimation ratio
     /// and maximum f32 input value.
     /// Returns a tuple (input_scaling, output_scaling).
@@ -246,6 +286,14 @@ impl<const N: usize> CicDuc<N> {
         }
     }
 
+    /// Advance mixer phase as if a number of output samples had been
+    /// produced, so that the phase stays continuous across
+    /// a discontinuity in output signal.
+    pub fn advance(&mut self, samples: i64) {
+        let len = self.sinetable.len() as i64;
+        self.phase = (self.phase as i64 + (samples.rem_euclid(len) * self.freq as i64)).rem_euclid(len) as usize;
+    }
+
     /// Compute scaling factors for a given interpolation ratio
     /// and maximum f32 input value.
     /// Returns a tuple (input_scaling, output_scaling).
@@ -335,4 +383,26 @@ mod tests {
             }
         }
     }
+
+    #[test]
+    fn test_advance() {
+        let sinetable = make_sinetable(100);
+        for freq in [-3, 7] {
+            // Advancing phase shall give the same result
+            // as processing the skipped samples.
+            let mut ddc1 = CicDdc::<4>::new(sinetable.clone(), freq);
+            let mut ddc2 = CicDdc::<4>::new(sinetable.clone(), freq);
+            ddc1.process(&[num::zero(); 123]);
+            ddc2.advance(123);
+            assert_eq!(ddc1.phase, ddc2.phase);
+            ddc2.advance(-123);
+            assert_eq!(ddc2.phase, 0);
+
+            let mut duc1 = CicDuc::<4>::new(sinetable.clone(), freq);
+            let mut duc2 = CicDuc::<4>::new(sinetable.clone(), freq);
+            duc1.process(num::zero(), &mut [num::zero(); 57]);
+            duc2.advance(57);
+            assert_eq!(duc1.phase, duc2.phase);
+        }
+    }
 }
@@ -0,0 +1,391 @@
+//! Burst detection based on training sequences.
+
+use num::Complex;
+use crate::burst::*;
+use super::modem::{DemodulatedSlot, GUARD_SYMBOLS, SLOT_MARGIN, SLOT_SYMBOLS, SUBSLOT_SYMBOLS, SYMBOLRATE};
+
+/// Normal training sequence 1 (n)
+const NORMAL_TS1: [u8; 22] = [1,1, 0,1, 0,0, 0,0, 1,1, 1,0, 1,0, 0,1, 1,1, 0,1, 0,0];
+/// Normal training sequence 2 (p)
+const NORMAL_TS2: [u8; 22] = [0,1, 1,1, 1,0, 1,0, 0,1, 0,0, 0,0, 1,1, 0,1, 1,1, 1,0];
+/// Normal training sequence 3 (q).
+/// Continuous down-link bursts begin with q11-q22 and end with q1-q10.
+const NORMAL_TS3: [u8; 22] = [1,0, 1,1, 0,1, 1,1, 0,0, 0,0, 0,1, 1,0, 1,0, 1,1, 0,1];
+/// Extended training sequence (x)
+const EXTENDED_TS: [u8; 30] = [1,0, 0,1, 1,1, 0,1, 0,0, 0,0, 1,1, 1,0, 1,0, 0,1, 1,1, 0,1, 0,0, 0,0, 1,1];
+/// Synchronization training sequence (y)
+const SYNC_TS: [u8; 38] = [1,1, 0,0, 0,0, 0,1, 1,0, 0,1, 1,1, 0,0, 1,1, 1,0, 1,0, 0,1, 1,1, 0,0, 0,0, 0,1, 1,0, 0,1, 1,1];
+
+/// Position of normal training sequence in a continuous down-link burst
+/// as a symbol number from the beginning of the slot.
+const DL_NORMAL_TS_POS: usize = 122;
+/// Position of synchronization training sequence
+/// in a synchronization continuous down-link burst.
+const DL_SYNC_TS_POS: usize = 107;
+/// Position of normal training sequence 3 at the end
+/// of a continuous down-link burst.
+const DL_NORMAL_TS3_POS: usize = SLOT_SYMBOLS - 5;
+/// Position of normal training sequence in a normal up-link burst.
+const UL_NORMAL_TS_POS: usize = GUARD_SYMBOLS + 110;
+/// Position of extended training sequence in a control up-link burst
+/// in the first subslot.
+const UL_CONTROL_TS_POS: usize = GUARD_SYMBOLS + 44;
+/// Position of normal training sequence in a direct mode normal burst.
+const DMO_NORMAL_TS_POS: usize = GUARD_SYMBOLS + 115;
+/// Position of synchronization training sequence
+/// in a direct mode synchronization burst.
+const DMO_SYNC_TS_POS: usize = GUARD_SYMBOLS + 107;
+
+/// Minimum correlation (normalized to 1.0 for a perfect match)
+/// with a training sequence to consider a burst detected.
+const DETECTION_THRESHOLD: f32 = 0.75;
+
+/// Which kinds of bursts are expected on a receive carrier.
+/// Some training sequences are located only a few symbols apart
+/// in different kinds of bursts, so it is not possible to
+/// reliably tell them apart without knowing what to expect.
+#[derive(Copy, Clone, Eq, PartialEq)]
+pub enum RxMode {
+    /// Continuous down-link bursts, i.e. receiving a base station.
+    Downlink,
+    /// Up-link bursts, i.e. receiving mobile stations.
+    Uplink,
+    /// Direct mode bursts.
+    Dmo,
+}
+
+impl RxMode {
+    pub fn name(self) -> &'static str {
+        match self {
+            RxMode::Downlink => "downlink",
+            RxMode::Uplink   => "uplink",
+            RxMode::Dmo      => "direct mode",
+        }
+    }
+}
+
+/// Convert bits to differentially detected symbols
+/// which the demodulator would produce from an ideal signal.
+pub fn bits_to_diff_symbols(bits: &[u8], symbols: &mut [Complex<f32>]) {
+    for (b, s) in bits.chunks_exact(2).zip(symbols.iter_mut()) {
+        let phase_change = match (b[0] != 0, b[1] != 0) {
+            (true,  true)  => -3,
+            (true,  false) => -1,
+            (false, false) =>  1,
+            (false, true)  =>  3,
+        };
+        *s = Complex::from_polar(1.0, phase_change as f32 * std::f32::consts::FRAC_PI_4);
+    }
+}
+
+/// Training sequence converted to the form used for correlation.
+struct TrainingSequence(Vec<Complex<f32>>);
+
+impl TrainingSequence {
+    fn new(bits: &[u8]) -> Self {
+        let mut symbols = vec![num::zero(); bits.len() / 2];
+        bits_to_diff_symbols(bits, &mut symbols[..]);
+        Self(symbols)
+    }
+
+    /// Correlate with demodulated symbols starting from given index.
+    /// Symbols beyond the end of the slot are counted as mismatches.
+    fn correlate(&self, symbols: &[Complex<f32>], start: usize) -> Complex<f32> {
+        self.0.iter().zip(symbols.iter().skip(start))
+            .map(|(t, s)| s * t.conj())
+            .sum()
+    }
+}
+
+/// Result of a training sequence search.
+#[derive(Copy, Clone)]
+struct Detection {
+    /// Correlation normalized so that a perfect match gives 1.0.
+    correlation: f32,
+    /// Timing offset from nominal position in symbols.
+    offset: isize,
+    /// Carrier frequency offset estimated from phase of correlation.
+    cfo: f32,
+}
+
+impl Detection {
+    fn detected(&self) -> bool {
+        self.correlation >= DETECTION_THRESHOLD
+    }
+}
+
+/// Search for training sequence(s) within the margin around
+/// their nominal positions given as symbol numbers within a slot.
+/// If several training sequences are given, their correlations
+/// are combined for the same timing offset.
+fn search(slot: &DemodulatedSlot, sequences: &[(&TrainingSequence, usize)]) -> Detection {
+    let margin = SLOT_MARGIN as isize;
+    let total_len: usize = sequences.iter().map(|(ts, _)| ts.0.len()).sum();
+    let mut best = Detection { correlation: f32::MIN, offset: 0, cfo: 0.0 };
+    for offset in -margin ..= margin {
+        let c: Complex<f32> = sequences.iter().map(|(ts, position)| {
+            ts.correlate(&slot.symbols, ((SLOT_MARGIN + position) as isize + offset) as usize)
+        }).sum::<Complex<f32>>() / total_len as f32;
+        if c.re > best.correlation {
+            best = Detection {
+                correlation: c.re,
+                offset,
+                // Frequency offset rotates each differentially
+                // detected symbol by the phase change in a symbol period.
+                cfo: c.arg() * (SYMBOLRATE / std::f64::consts::TAU) as f32,
+            };
+        }
+    }
+    best
+}
+
+pub struct BurstDetector {
+    mode: RxMode,
+    /// Offset added to RSSI to convert dBFS to dBm.
+    rssi_offset: f32,
+    normal_ts1: TrainingSequence,
+    normal_ts2: TrainingSequence,
+    normal_ts3: TrainingSequence,
+    extended_ts: TrainingSequence,
+    sync_ts: TrainingSequence,
+}
+
+impl BurstDetector {
+    pub fn new(mode: RxMode, rssi_offset: f32) -> Self {
+        Self {
+            mode,
+            rssi_offset,
+            normal_ts1: TrainingSequence::new(&NORMAL_TS1),
+            normal_ts2: TrainingSequence::new(&NORMAL_TS2),
+            normal_ts3: TrainingSequence::new(&NORMAL_TS3),
+            extended_ts: TrainingSequence::new(&EXTENDED_TS),
+            sync_ts: TrainingSequence::new(&SYNC_TS),
+        }
+    }
+
+    /// Kind of bursts this detector looks for.
+    pub fn mode(&self) -> RxMode {
+        self.mode
+    }
+
+    /// Detect a burst in demodulated slot.
+    pub fn detect(&self, slot: &DemodulatedSlot) -> RxBurst {
+        match self.mode {
+            RxMode::Downlink => self.detect_dl(slot),
+            RxMode::Uplink => self.detect_ul(slot),
+            RxMode::Dmo => self.detect_dmo(slot),
+        }
+    }
+
+    fn detect_dl(&self, slot: &DemodulatedSlot) -> RxBurst {
+        // Normal training sequence 3 continues from the end of a burst
+        // to the beginning of the next one, so it can be used
+        // in addition to the training sequence in the middle.
+        let q = (&self.normal_ts3, DL_NORMAL_TS3_POS);
+        let n1 = search(slot, &[(&self.normal_ts1, DL_NORMAL_TS_POS), q]);
+        let n2 = search(slot, &[(&self.normal_ts2, DL_NORMAL_TS_POS), q]);
+        let sync = search(slot, &[(&self.sync_ts, DL_SYNC_TS_POS), q]);
+        match best_of(&[n1, n2, sync]) {
+            Some((0, d)) => RxBurst::DlNormal1(self.make_burst(slot, 0, &d)),
+            Some((1, d)) => RxBurst::DlNormal2(self.make_burst(slot, 0, &d)),
+            Some((_, d)) => RxBurst::DlSync(self.make_burst(slot, 0, &d)),
+            None => RxBurst::None,
+        }
+    }
+
+    fn detect_ul(&self, slot: &DemodulatedSlot) -> RxBurst {
+        let n1 = search(slot, &[(&self.normal_ts1, UL_NORMAL_TS_POS)]);
+        let n2 = search(slot, &[(&self.normal_ts2, UL_NORMAL_TS_POS)]);
+        let x1 = search(slot, &[(&self.extended_ts, UL_CONTROL_TS_POS)]);
+        let x2 = search(slot, &[(&self.extended_ts, UL_CONTROL_TS_POS + SUBSLOT_SYMBOLS)]);
+        match best_of(&[n1, n2, x1, x2]) {
+            Some((0, d)) => RxBurst::UlNormal1(self.make_burst(slot, GUARD_SYMBOLS, &d)),
+            Some((1, d)) => RxBurst::UlNormal2(self.make_burst(slot, GUARD_SYMBOLS, &d)),
+            Some(_) => RxBurst::Subslots([
+                if x1.detected() {
+                    RxSubslotBurst::UlControl(self.make_burst(slot, GUARD_SYMBOLS, &x1))
+                } else {
+                    RxSubslotBurst::None
+                },
+                if x2.detected() {
+                    RxSubslotBurst::UlControl(self.make_burst(slot, GUARD_SYMBOLS + SUBSLOT_SYMBOLS, &x2))
+                } else {
+                    RxSubslotBurst::None
+                },
+            ]),
+            None => RxBurst::None,
+        }
+    }
+
+    fn detect_dmo(&self, slot: &DemodulatedSlot) -> RxBurst {
+        let n1 = search(slot, &[(&self.normal_ts1, DMO_NORMAL_TS_POS)]);
+        let n2 = search(slot, &[(&self.normal_ts2, DMO_NORMAL_TS_POS)]);
+        let sync = search(slot, &[(&self.sync_ts, DMO_SYNC_TS_POS)]);
+        match best_of(&[n1, n2, sync]) {
+            Some((0, d)) => RxBurst::DmoNormal1(self.make_burst(slot, GUARD_SYMBOLS, &d)),
+            Some((1, d)) => RxBurst::DmoNormal2(self.make_burst(slot, GUARD_SYMBOLS, &d)),
+            Some((_, d)) => RxBurst::DmoSync(self.make_burst(slot, GUARD_SYMBOLS, &d)),
+            None => RxBurst::None,
+        }
+    }
+
+    /// Make a received burst from demodulated slot.
+    /// position is the nominal first symbol of the burst within a slot.
+    fn make_burst<B: FromDemodulated>(&self, slot: &DemodulatedSlot, position: usize, detection: &Detection) -> B {
+        let first = ((SLOT_MARGIN + position) as isize + detection.offset) as usize;
+        let mut burst = B::new();
+        let bits = burst.bits_mut();
+        slot.hard_bits(first, bits);
+        let symbols = bits.len() / 2;
+        let power = slot.power[first .. first + symbols].iter().sum::<f32>() / symbols as f32;
+        *burst.info_mut() = RxBurstInfo {
+            timestamp: slot.times[first],
+            rssi: 10.0 * power.log10() + self.rssi_offset,
+            cfo: detection.cfo,
+        };
+        burst
+    }
+}
+
+/// Find the best detected training sequence.
+/// Returns index of the best result and the result itself.
+fn best_of(results: &[Detection]) -> Option<(usize, Detection)> {
+    let mut best: Option<(usize, Detection)> = None;
+    for (i, d) in results.iter().enumerate() {
+        if d.detected() && best.is_none_or(|(_, b)| d.correlation > b.correlation) {
+            best = Some((i, *d));
+        }
+    }
+    best
+}
+
+/// Common interface to received burst types
+/// used to fill them from a demodulated slot.
+trait FromDemodulated {
+    fn new() -> Self;
+    fn info_mut(&mut self) -> &mut RxBurstInfo;
+    fn bits_mut(&mut self) -> &mut [u8];
+}
+
+macro_rules! impl_from_demodulated {
+    ($burst_type:ty, $bits:literal) => {
+        impl FromDemodulated for $burst_type {
+            fn new() -> Self {
+                Self {
+                    info: RxBurstInfo::default(),
+                    bits: [0; $bits],
+                }
+            }
+            fn info_mut(&mut self) -> &mut RxBurstInfo { &mut self.info }
+            fn bits_mut(&mut self) -> &mut [u8] { &mut self.bits }
+        }
+    }
+}
+
+impl_from_demodulated!(RxDlBurst, 510);
+impl_from_demodulated!(RxUlNormalBurst, 462);
+impl_from_demodulated!(RxUlControlBurst, 206);
+impl_from_demodulated!(RxDmoBurst, 470);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::slot::SlotNumber;
+    use crate::dsp::modem::SLOT_WINDOW;
+
+    /// Make a demodulated slot with given bits at given symbol position.
+    fn make_slot(bits: &[u8], position: usize) -> DemodulatedSlot {
+        let mut slot = DemodulatedSlot {
+            slot: SlotNumber::new(1, 1, 1),
+            time: 0,
+            symbols: [num::zero(); SLOT_WINDOW],
+            power: [1.0; SLOT_WINDOW],
+            times: [0; SLOT_WINDOW],
+        };
+        bits_to_diff_symbols(bits, &mut slot.symbols[position..]);
+        for (i, t) in slot.times.iter_mut().enumerate() {
+            *t = i as i64 * 1000;
+        }
+        slot
+    }
+
+    /// Make pseudorandom bits for a burst.
+    fn random_bits(bits: &mut [u8]) {
+        let mut rng: u32 = 1;
+        for b in bits.iter_mut() {
+            rng = rng.wrapping_mul(1103515245).wrapping_add(12345);
+            *b = (rng >> 30) as u8 & 1;
+        }
+    }
+
+    #[test]
+    fn test_detect_dl() {
+        for (ts, ts_pos) in [(&NORMAL_TS1[..], 244), (&NORMAL_TS2[..], 244), (&SYNC_TS[..], 214)] {
+            for offset in [-5isize, 0, 3] {
+                // Include beginning of the next burst
+                // which starts with the rest of normal training sequence 3.
+                let mut bits = [0u8; 510 + 12];
+                random_bits(&mut bits);
+                bits[ts_pos .. ts_pos + ts.len()].copy_from_slice(ts);
+                bits[0 .. 12].copy_from_slice(&NORMAL_TS3[10..]);
+                bits[500 .. 522].copy_from_slice(&NORMAL_TS3);
+                let slot = make_slot(&bits, (SLOT_MARGIN as isize + offset) as usize);
+                let detected = BurstDetector::new(RxMode::Downlink, 0.0).detect(&slot);
+                let burst = match (ts.len(), ts[0], detected) {
+                    (22, 1, RxBurst::DlNormal1(burst)) => burst,
+                    (22, 0, RxBurst::DlNormal2(burst)) => burst,
+                    (38, _, RxBurst::DlSync(burst)) => burst,
+                    _ => panic!("Wrong burst type detected"),
+                };
+                assert!(burst.bits == bits[..510]);
+                assert_eq!(burst.info.timestamp, (SLOT_MARGIN as i64 + offset as i64) * 1000);
+                assert!(burst.info.rssi.abs() < 0.01);
+                assert!(burst.info.cfo.abs() < 0.01);
+            }
+        }
+    }
+
+    #[test]
+    fn test_cfo_estimate() {
+        let mut bits = [0u8; 462];
+        random_bits(&mut bits);
+        bits[220 .. 242].copy_from_slice(&NORMAL_TS2);
+        let mut slot = make_slot(&bits, SLOT_MARGIN + GUARD_SYMBOLS);
+        // Rotate symbols to simulate a frequency offset.
+        let cfo = 300.0;
+        let rotation = Complex::from_polar(1.0, (std::f64::consts::TAU * cfo / SYMBOLRATE) as f32);
+        for s in slot.symbols.iter_mut() {
+            *s *= rotation;
+        }
+        match BurstDetector::new(RxMode::Uplink, 0.0).detect(&slot) {
+            RxBurst::UlNormal2(burst) => {
+                assert!(burst.bits == bits);
+                assert!((burst.info.cfo - cfo as f32).abs() < 1.0);
+            },
+            _ => panic!("Wrong burst type detected"),
+        }
+    }
+
+    #[test]
+    fn test_detect_ul_subslots() {
+        let mut bits = [0u8; 206];
+        random_bits(&mut bits);
+        bits[88 .. 118].copy_from_slice(&EXTENDED_TS);
+        // Burst in second subslot only
+        let slot = make_slot(&bits, SLOT_MARGIN + GUARD_SYMBOLS + SUBSLOT_SYMBOLS + 2);
+        match BurstDetector::new(RxMode::Uplink, 0.0).detect(&slot) {
+            RxBurst::Subslots([RxSubslotBurst::None, RxSubslotBurst::UlControl(burst)]) => {
+                assert!(burst.bits == bits);
+            },
+            _ => panic!("Wrong burst type detected"),
+        }
+    }
+
+    #[test]
+    fn test_detect_none() {
+        let slot = make_slot(&[], 0);
+        for mode in [RxMode::Downlink, RxMode::Uplink, RxMode::Dmo] {
+            assert!(matches!(BurstDetector::new(mode, 0.0).detect(&slot), RxBurst::None));
+        }
+    }
+}
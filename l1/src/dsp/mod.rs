@@ -2,17 +2,21 @@
 
 use num::Complex;
 
-use crate::{L1Callbacks, SlotNumber, TxBurst};
+use crate::{L1Callbacks, L1RxCommands, L1TxCommands, SlotNumber, TxBurst};
+use crate::config::{CarrierConfig, CarrierDirection, CarrierRole, L1Config};
+use crate::timebase::Timebase;
 
 mod modem;
-use modem::Modulator;
+use modem::{Demodulator, DemodulatedSlot, Modulator};
+
+mod detector;
+use detector::BurstDetector;
+pub use detector::RxMode;
 
 pub mod cic;
 mod fir;
-
-/// Modem sample duration in nanoseconds.
-/// Modem runs at a sample rate of 4*18 kHz.
-const MODEM_SAMPLE_NS: i64 = 13889;
+mod resampler;
+use resampler::Resampler;
 
 type RxDdc = cic::CicDdc<4>;
 type TxDuc = cic::CicDuc<4>;
@@ -20,7 +24,7 @@ type TxDuc = cic::CicDuc<4>;
 /// Combined pulse shaping and CIC compensation filter
 /// for a rate of 4 samples per symbol.
 /// Coefficients from design_channel_filter.py
-const CHANNEL_FILTER_TAPS: [f32; 16] = [
+pub(crate) const CHANNEL_FILTER_TAPS: [f32; 16] = [
     0.27991672,
     0.20776464,
     0.09827440,
@@ -39,16 +43,43 @@ const CHANNEL_FILTER_TAPS: [f32; 16] = [
     0.00273298
 ];
 
+/// Bandwidth of a channel (Hz)
+const CHANNEL_BANDWIDTH: f64 = 25000.0;
+
+/// Range of supported CIC decimation factors.
+/// With lower factors, CIC output rate may differ too much from
+/// modem sample rate for the resampler. With higher factors,
+/// bit growth in CIC leaves too little resolution for input samples.
+const MIN_CIC_FACTOR: usize = 4;
+const MAX_CIC_FACTOR: usize = 500;
+
 /// Common data used for all RX and TX carriers
 struct DspCommon {
     // SDR I/Q sample rate (Hz)
     radio_fs: f64,
+    // Conversion between SDR sample counts and timestamps
+    timebase: Timebase,
+    // Receive center frequency (Hz)
+    rx_freq: f64,
+    // Transmit center frequency (Hz)
+    tx_freq: f64,
+    // Difference between transmit and receive frequencies of carriers (Hz)
+    duplex_spacing: f64,
     // Channel spacing for carriers (Hz)
     channel_spacing: f64,
     // CIC decimation and interpolation factor
     cic_factor: usize,
+    // Sample rate at CIC output (Hz).
+    // If it differs from modem sample rate, a resampler is used
+    // between CIC and modem.
+    cic_fs: f64,
     // CIC DDC scaling factors
     ddc_scale: (f32, f32),
+    // Delay of receive signal processing in nanoseconds,
+    // subtracted from timestamps of received signal.
+    rx_delay: i64,
+    // Offset added to RSSI to convert dBFS to dBm.
+    rssi_offset: f32,
     // CIC DUC scaling factors
     duc_scale: (f32, f32),
     // DUC input scaling multiplied by other scaling factors
@@ -56,28 +87,65 @@ struct DspCommon {
     duc_input_scaling_combined: f32,
     // Sine table for DDC/DUC
     sine_table: cic::SineTableType,
+    // Number of sine table bins per channel spacing
+    sine_bins_per_channel: isize,
     // Channel filter taps
     filter_taps: fir::SymmetricRealTaps,
 }
 
+impl DspCommon {
+    /// Convert a carrier frequency to an offset from center frequency
+    /// in multiples of channel spacing, checking that the carrier is
+    /// on the channel raster and fits within the band of the radio.
+    fn carrier_channel(&self, freq: f64, center: f64) -> Result<isize, String> {
+        let offset = freq - center;
+        let channel = offset / self.channel_spacing;
+        if (channel - channel.round()).abs() > 1e-6 {
+            return Err(format!(
+                "carrier frequency {} Hz is not on the {} Hz channel raster around center frequency {} Hz",
+                freq, self.channel_spacing, center));
+        }
+        if offset.abs() + CHANNEL_BANDWIDTH * 0.5 > self.radio_fs * 0.5 {
+            return Err(format!(
+                "carrier frequency {} Hz does not fit in the {} Hz wide band around center frequency {} Hz",
+                freq, self.radio_fs, center));
+        }
+        Ok(channel.round() as isize)
+    }
+
+    /// Make a resampler from one rate to another,
+    /// or None if the rates are equal.
+    fn resampler(&self, fs_in: f64, fs_out: f64) -> Option<Resampler> {
+        if fs_in == fs_out { None } else { Some(Resampler::new(fs_in, fs_out)) }
+    }
+}
+
 struct TxCarrier {
     id: i32,
+    // Offset from center frequency in multiples of channel spacing
+    channel: isize,
     duc: TxDuc,
+    // Resampler from modem sample rate to CIC sample rate
+    resampler: Option<Resampler>,
     filter: fir::FirCf32Sym,
     modulator: Modulator,
 }
 
 impl TxCarrier {
+    /// channel is the carrier frequency offset
+    /// in multiples of channel spacing.
     pub fn new(
         common: &DspCommon,
         id: i32,
-        carrier_freq: f64,
+        channel: isize,
     ) -> Self {
         Self {
             id,
-            duc: TxDuc::new(common.sine_table.clone(), (carrier_freq / common.channel_spacing).round() as isize),
+            channel,
+            duc: TxDuc::new(common.sine_table.clone(), channel * common.sine_bins_per_channel),
+            resampler: common.resampler(modem::FS, common.cic_fs),
             filter: fir::FirCf32Sym::new(common.filter_taps.clone()),
-            modulator: Modulator::new(),
+            modulator: Modulator::new(modem::FS),
         }
     }
 
@@ -90,32 +158,197 @@ impl TxCarrier {
         buf: &mut [cic::BufferType],
         callbacks: &L1Callbacks,
     ) {
-        let mut modulated = self.modulator.sample(time,
-            &mut |slot: SlotNumber, slot_time: i64, burst: &mut TxBurst| {
-                (callbacks.tx_burst)(callbacks.tx_burst_arg, self.id, slot, slot_time, burst)
-            }
-        );
-        modulated = self.filter.sample(modulated);
+        let id = self.id;
+        let modulator = &mut self.modulator;
+        let filter = &mut self.filter;
+        let mut modulate = |time: i64| {
+            let modulated = modulator.sample(time,
+                &mut |commands: &mut L1TxCommands| {
+                    (callbacks.tx_cmd)(callbacks.tx_cmd_arg, id, commands)
+                },
+                &mut |slot: SlotNumber, slot_time: i64, burst: &mut TxBurst| {
+                    (callbacks.tx_burst)(callbacks.tx_burst_arg, id, slot, slot_time, burst)
+                }
+            );
+            filter.sample(modulated)
+        };
+        let filtered = match &mut self.resampler {
+            None => modulate(time),
+            Some(resampler) => resampler.pull(&mut |offset| {
+                modulate(time + (offset * 1e9 / modem::FS).round() as i64)
+            }),
+        };
         self.duc.process(
-            cic::cf32_to_sample(modulated, common.duc_input_scaling_combined),
+            cic::cf32_to_sample(filtered, common.duc_input_scaling_combined),
             buf);
     }
+
+    /// Skip over a discontinuity of a number of radio samples.
+    /// Modulator follows timestamps, so only the DUC needs adjusting.
+    pub fn skip(&mut self, samples: i64) {
+        self.duc.advance(samples);
+    }
+}
+
+/// Receive channel selection: down-conversion,
+/// resampling and channel filtering.
+struct RxChannelFilter {
+    ddc: RxDdc,
+    // Resampler from CIC sample rate to modem sample rate
+    resampler: Option<Resampler>,
+    filter: fir::FirCf32Sym,
+}
+
+impl RxChannelFilter {
+    /// Process one CIC processing block of samples.
+    /// Buffer length shall be equal to CIC decimation ratio.
+    /// output is called with each channel filtered sample at modem
+    /// sample rate and its timestamp.
+    fn process(
+        &mut self,
+        common: &DspCommon,
+        time: i64,
+        buf: &[cic::BufferType],
+        output: &mut dyn FnMut(i64, Complex<f32>),
+    ) {
+        let sample = cic::sample_to_cf32(self.ddc.process(buf), common.ddc_scale.1);
+        let filter = &mut self.filter;
+        match &mut self.resampler {
+            None => output(time, filter.sample(sample)),
+            Some(resampler) => resampler.push(sample, &mut |resampled, offset| {
+                output(time - (offset * 1e9 / common.cic_fs).round() as i64, filter.sample(resampled))
+            }),
+        }
+    }
+}
+
+struct RxCarrier {
+    id: i32,
+    // Offset from center frequency in multiples of channel spacing
+    channel: isize,
+    channel_filter: RxChannelFilter,
+    demodulator: Demodulator,
+    detector: BurstDetector,
+}
+
+impl RxCarrier {
+    /// channel is the carrier frequency offset
+    /// in multiples of channel spacing.
+    pub fn new(
+        common: &DspCommon,
+        id: i32,
+        channel: isize,
+        mode: RxMode,
+    ) -> Self {
+        Self {
+            id,
+            channel,
+            channel_filter: RxChannelFilter {
+                ddc: RxDdc::new(common.sine_table.clone(), channel * common.sine_bins_per_channel),
+                resampler: common.resampler(common.cic_fs, modem::FS),
+                filter: fir::FirCf32Sym::new(common.filter_taps.clone()),
+            },
+            demodulator: Demodulator::new(),
+            detector: BurstDetector::new(mode, common.rssi_offset),
+        }
+    }
+
+    /// Process one CIC processing block of samples.
+    /// Buffer length shall be equal to CIC decimation ratio.
+    pub fn process(
+        &mut self,
+        common: &DspCommon,
+        time: i64,
+        buf: &[cic::BufferType],
+        callbacks: &L1Callbacks,
+    ) {
+        let id = self.id;
+        let demodulator = &mut self.demodulator;
+        let detector = &mut self.detector;
+        self.channel_filter.process(common, time, buf, &mut |time: i64, sample| {
+            demodulator.sample(time - common.rx_delay, sample,
+                &mut |commands: &mut L1RxCommands| {
+                    (callbacks.rx_cmd)(callbacks.rx_cmd_arg, id, commands)
+                },
+                &mut |demodulated: &DemodulatedSlot| {
+                    let burst = detector.detect(demodulated);
+                    (callbacks.rx_burst)(callbacks.rx_burst_arg, id, demodulated.slot, demodulated.time, &burst)
+                }
+            );
+        });
+    }
+
+    /// Skip over a discontinuity of a number of radio samples.
+    pub fn skip(&mut self, common: &DspCommon, samples: i64) {
+        self.channel_filter.ddc.advance(samples);
+        self.demodulator.skip((samples as f64 * modem::FS / common.radio_fs).round() as i64);
+    }
+}
+
+/// CIC decimation and interpolation factor for a radio sample rate.
+/// Nearest integer ratio is used, so that the resampler
+/// only needs to make a small adjustment.
+pub fn cic_factor(radio_fs: f64) -> usize {
+    (radio_fs / modem::FS).round().max(1.0) as usize
+}
+
+/// Compute group delay of receive signal processing in nanoseconds.
+fn rx_delay(radio_fs: f64, cic_factor: usize) -> i64 {
+    // Channel filter is symmetric with an even number of taps,
+    // so its delay is a half-integer number of samples.
+    let filter_delay = (CHANNEL_FILTER_TAPS.len() as f64 * 2.0 - 1.0) * 0.5 / modem::FS;
+    // CIC filter with N+1 stages delays by (N+1)*(R-1)/2 input samples.
+    let cic_delay = 5.0 * (cic_factor as f64 - 1.0) * 0.5 / radio_fs;
+    ((filter_delay + cic_delay) * 1e9).round() as i64
 }
 
 pub struct L1Dsp {
     common: DspCommon,
+    rx_carriers: Vec<RxCarrier>,
     tx_carriers: Vec<TxCarrier>,
+    // Carrier index given to the next added carrier
+    next_id: i32,
+    // Receive timestamp expected for the next block,
+    // used to detect discontinuities in the signal.
+    next_rx_time: Option<i64>,
+    // Buffer for one CIC processing block
+    cicbuf: Vec<cic::BufferType>,
 }
 
 impl L1Dsp {
-    pub fn new(radio_fs: f64) -> Self {
+    /// Initialize signal processing with carriers given in configuration.
+    /// Returns an error if sample rate, block length
+    /// or carrier frequencies are invalid.
+    pub fn new(conf: &L1Config) -> Result<Self, String> {
+        let radio_fs = conf.fs;
         let channel_spacing: f64 = 12500.0;
-        let cic_factor = (radio_fs / modem::FS).round() as usize;
+        let min_fs = MIN_CIC_FACTOR as f64 * modem::FS;
+        let max_fs = MAX_CIC_FACTOR as f64 * modem::FS;
+        if !(min_fs..=max_fs).contains(&radio_fs) {
+            return Err(format!(
+                "sample rate {} Hz is outside the supported range from {} Hz to {} Hz",
+                radio_fs, min_fs, max_fs));
+        }
+        let cic_factor = cic_factor(radio_fs);
+        let blocklen = conf.blocklen();
+        if blocklen % cic_factor != 0 {
+            return Err(format!(
+                "blocklen {} is not a multiple of CIC decimation factor {} used at sample rate {} Hz",
+                blocklen, cic_factor, radio_fs));
+        }
+        let (sine_table, sine_bins_per_channel) = cic::make_sinetable_freq(radio_fs, channel_spacing)?;
         let common = DspCommon {
             radio_fs: radio_fs,
+            timebase: Timebase::new(radio_fs),
+            rx_freq: conf.rx_freq,
+            tx_freq: conf.tx_freq,
+            duplex_spacing: conf.duplex_spacing,
             channel_spacing: channel_spacing,
             cic_factor: cic_factor,
+            cic_fs: radio_fs / cic_factor as f64,
             ddc_scale: RxDdc::scaling(cic_factor, 2.0),
+            rx_delay: rx_delay(radio_fs, cic_factor),
+            rssi_offset: conf.rssi_offset as f32,
             // Output amplitude is designed to stay below 1.0, but CIC
             // compensation filter may result in somewhat higher input values,
             // so specify 2.0 as maximum input to have plenty of margin.
@@ -123,45 +356,454 @@ impl L1Dsp {
             // Computed each time process() is run to also work correctly
             // in case we end up adding more carriers after initialization.
             duc_input_scaling_combined: 0.0,
-            sine_table: cic::make_sinetable_freq(radio_fs, channel_spacing),
+            sine_table: sine_table,
+            sine_bins_per_channel: sine_bins_per_channel,
             filter_taps: fir::convert_symmetric_real_taps(&CHANNEL_FILTER_TAPS),
         };
 
-        Self {
-            tx_carriers: vec![TxCarrier::new(&common, 0, 25000.0), TxCarrier::new(&common, 1, 50000.0)],
+        let mut dsp = Self {
+            cicbuf: vec![num::zero(); common.cic_factor],
             common: common,
+            rx_carriers: Vec::new(),
+            tx_carriers: Vec::new(),
+            next_id: 0,
+            next_rx_time: None,
+        };
+        for carrier in conf.carriers.iter() {
+            dsp.add_carrier(carrier)?;
         }
+        Ok(dsp)
     }
 
+    /// Add RX and/or TX carrier(s) according to carrier configuration.
+    /// Returns the carrier index passed to callbacks.
+    /// Carriers are numbered in the order they are added,
+    /// and indexes of removed carriers are not reused.
+    pub fn add_carrier(&mut self, carrier: &CarrierConfig) -> Result<i32, String> {
+        // Direct mode carriers are simplex, so there is no duplex spacing.
+        let rx_freq = match carrier.role {
+            CarrierRole::Dmo => carrier.freq,
+            _ => carrier.freq - self.common.duplex_spacing,
+        };
+        let (rx_channel, tx_channel) = match carrier.direction {
+            CarrierDirection::Rx => (
+                Some(self.common.carrier_channel(carrier.freq, self.common.rx_freq)?),
+                None),
+            CarrierDirection::Tx => (
+                None,
+                Some(self.common.carrier_channel(carrier.freq, self.common.tx_freq)?)),
+            CarrierDirection::Both => (
+                Some(self.common.carrier_channel(rx_freq, self.common.rx_freq)?),
+                Some(self.common.carrier_channel(carrier.freq, self.common.tx_freq)?)),
+        };
+        let rx_mode = match carrier.role {
+            CarrierRole::Dmo => RxMode::Dmo,
+            CarrierRole::MobileStation => RxMode::Downlink,
+            CarrierRole::MainControl | CarrierRole::Traffic => RxMode::Uplink,
+        };
+        let id = self.next_id;
+        self.next_id += 1;
+        if let Some(channel) = rx_channel {
+            self.rx_carriers.push(RxCarrier::new(&self.common, id, channel, rx_mode));
+        }
+        if let Some(channel) = tx_channel {
+            self.tx_carriers.push(TxCarrier::new(&self.common, id, channel));
+        }
+        Ok(id)
+    }
+
+    /// Describe sample rates and carrier frequencies
+    /// in human readable form, one item per line.
+    pub fn frequency_plan(&self) -> String {
+        let c = &self.common;
+        let mut plan = format!("Sample rate {} Hz, CIC factor {}, CIC output rate {:.3} Hz",
+            c.radio_fs, c.cic_factor, c.cic_fs);
+        if c.cic_fs != modem::FS {
+            plan += &format!(", resampled to {} Hz", modem::FS);
+        }
+        plan += &format!("\nSine table of {} entries, {} per {} Hz channel",
+            c.sine_table.len(), c.sine_bins_per_channel, c.channel_spacing);
+        let carriers = self.rx_carriers.iter()
+            .map(|carrier| ("RX", carrier.id, carrier.channel, c.rx_freq, Some(carrier.detector.mode())))
+            .chain(self.tx_carriers.iter().map(|carrier| ("TX", carrier.id, carrier.channel, c.tx_freq, None)));
+        for (direction, id, channel, center, mode) in carriers {
+            let offset = channel as f64 * c.channel_spacing;
+            plan += &format!("\n{} carrier {}: {} Hz, channel {:+} ({:+} Hz from center frequency {} Hz)",
+                direction, id, center + offset, channel, offset, center);
+            if let Some(mode) = mode {
+                plan += &format!(", {} bursts", mode.name());
+            }
+        }
+        plan
+    }
+
+    /// Remove RX and TX carriers with a given carrier index.
+    /// Returns false if there was no such carrier.
+    pub fn remove_carrier(&mut self, id: i32) -> bool {
+        let carriers_before = self.rx_carriers.len() + self.tx_carriers.len();
+        self.rx_carriers.retain(|carrier| carrier.id != id);
+        self.tx_carriers.retain(|carrier| carrier.id != id);
+        self.rx_carriers.len() + self.tx_carriers.len() != carriers_before
+    }
+
+    /// Resynchronize processing state after a discontinuity
+    /// of a number of radio samples in the signal.
+    fn skip(&mut self, samples: i64) {
+        log_debug!("Resynchronizing signal processing after a gap of {} samples", samples);
+        for carrier in self.rx_carriers.iter_mut() {
+            carrier.skip(&self.common, samples);
+        }
+        for carrier in self.tx_carriers.iter_mut() {
+            carrier.skip(samples);
+        }
+    }
+
+    /// Process a block of received signal and produce
+    /// a block of transmit signal. Lengths of the blocks may differ.
     pub fn process(
         &mut self,
-        buf: &mut [Complex<f32>],
+        rx_buf: &[Complex<f32>],
         rx_time: i64,
+        tx_buf: &mut [Complex<f32>],
         tx_time: i64,
         callbacks: &L1Callbacks,
     ) {
-        let mut rx_time_now = rx_time;
-        let mut tx_time_now = tx_time;
-
-        // TODO: allocate this buffer only once and store it in self.common
-        let mut cicbuf: Vec<cic::BufferType> = vec![num::zero(); self.common.cic_factor];
+        let timebase = self.common.timebase;
+        if let Some(expected) = self.next_rx_time {
+            if timebase.is_discontinuous(rx_time, expected) {
+                self.skip(timebase.ns_to_samples(rx_time - expected));
+            }
+        }
+        self.next_rx_time = Some(rx_time + timebase.samples_to_ns(rx_buf.len() as i64));
+        let cic_factor = self.common.cic_factor;
+        let cicbuf = &mut self.cicbuf[..];
 
         // Adjusted to keep peak magnitude just below 1.0.
         // Reduce carrier amplitude with number of carriers
         // to keep worst case peaks after combining just below 1.0.
-        let modulator_scaling = 0.68 * modem::SPS as f32 / self.tx_carriers.len() as f32;
+        let modulator_scaling = 0.68 * modem::SPS as f32 / self.tx_carriers.len().max(1) as f32;
         self.common.duc_input_scaling_combined = modulator_scaling * self.common.duc_scale.0;
 
-        for bufblock in buf.chunks_exact_mut(self.common.cic_factor) {
+        // Timestamp of each modem sample is computed from its position
+        // in the block, so rounding errors do not accumulate.
+        for (i, bufblock) in rx_buf.chunks_exact(cic_factor).enumerate() {
+            let time = rx_time + timebase.samples_to_ns((i * cic_factor) as i64);
+            cic::cf32_to_buf(bufblock, cicbuf, self.common.ddc_scale.0);
+            for carrier in self.rx_carriers.iter_mut() {
+                carrier.process(&self.common, time, cicbuf, callbacks);
+            }
+        }
+
+        let mut tx_blocks = tx_buf.chunks_exact_mut(cic_factor);
+        for (i, bufblock) in (&mut tx_blocks).enumerate() {
+            let time = tx_time + timebase.samples_to_ns((i * cic_factor) as i64);
             for v in cicbuf.iter_mut() { *v = num::zero(); }
             for carrier in self.tx_carriers.iter_mut() {
-                carrier.process(&self.common, tx_time_now, &mut cicbuf[..], callbacks);
+                carrier.process(&self.common, time, cicbuf, callbacks);
+            }
+            cic::buf_to_cf32(cicbuf, bufblock, self.common.duc_scale.1);
+        }
+        // Block length should be a multiple of CIC interpolation ratio,
+        // but make sure the whole buffer is written anyway.
+        for v in tx_blocks.into_remainder() { *v = num::zero(); }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::RxBurst;
+
+    fn test_config(carriers: Vec<CarrierConfig>) -> L1Config {
+        L1Config {
+            fs: 1.8e6,
+            rx_freq: 390e6,
+            tx_freq: 400e6,
+            duplex_spacing: 10e6,
+            carriers: carriers,
+            ..L1Config::default()
+        }
+    }
+
+    #[test]
+    fn test_carrier_frequencies() {
+        let dsp = L1Dsp::new(&test_config(vec![
+            CarrierConfig { freq: 400.025e6, direction: CarrierDirection::Both, role: CarrierRole::MainControl },
+            CarrierConfig { freq: 389.9e6, direction: CarrierDirection::Rx, role: CarrierRole::Traffic },
+            CarrierConfig { freq: 399.5e6, direction: CarrierDirection::Tx, role: CarrierRole::Traffic },
+        ])).unwrap();
+        assert_eq!(dsp.rx_carriers.iter().map(|c| c.id).collect::<Vec<i32>>(), vec![0, 1]);
+        assert_eq!(dsp.tx_carriers.iter().map(|c| c.id).collect::<Vec<i32>>(), vec![0, 2]);
+        assert_eq!(dsp.common.carrier_channel(400.025e6, 400e6), Ok(2));
+        assert_eq!(dsp.common.carrier_channel(399.9e6, 400e6), Ok(-8));
+    }
+
+    #[test]
+    fn test_rx_channel_selection() {
+        let mut dsp = L1Dsp::new(&test_config(vec![
+            CarrierConfig { freq: 390.025e6, direction: CarrierDirection::Rx, role: CarrierRole::Traffic },
+            CarrierConfig { freq: 390.05e6, direction: CarrierDirection::Rx, role: CarrierRole::Traffic },
+        ])).unwrap();
+        let common = &dsp.common;
+
+        // Tone 1 kHz above the first carrier
+        let freq = 26e3;
+        let mut cicbuf: Vec<cic::BufferType> = vec![num::zero(); common.cic_factor];
+        let mut outputs: Vec<Vec<Complex<f32>>> = vec![Vec::new(); dsp.rx_carriers.len()];
+        for block in 0..400 {
+            let signal: Vec<Complex<f32>> = (0..common.cic_factor).map(|i| {
+                let t = (block * common.cic_factor + i) as f64 / common.radio_fs;
+                let phase = 2.0 * std::f64::consts::PI * freq * t;
+                Complex::new(0.5 * phase.cos() as f32, 0.5 * phase.sin() as f32)
+            }).collect();
+            cic::cf32_to_buf(&signal, &mut cicbuf[..], common.ddc_scale.0);
+            for (carrier, output) in dsp.rx_carriers.iter_mut().zip(outputs.iter_mut()) {
+                carrier.channel_filter.process(common, 0, &cicbuf[..], &mut |_, sample| output.push(sample));
             }
-            cic::buf_to_cf32(&cicbuf[..], bufblock, self.common.duc_scale.1);
-            // Increment timestamps for a 4*18 kHz sample rate.
-            // FIXME: This is not exact as it has been rounded to integer nanoseconds.
-            rx_time_now += MODEM_SAMPLE_NS;
-            tx_time_now += MODEM_SAMPLE_NS;
         }
+
+        // Skip filter transients
+        let power = |output: &[Complex<f32>]|
+            output[200..].iter().map(|s| s.norm_sqr()).sum::<f32>() / (output.len() - 200) as f32;
+        let wanted = power(&outputs[0]);
+        let adjacent = power(&outputs[1]);
+        // Passband gain is unity and adjacent channel is rejected
+        assert!((wanted - 0.25).abs() < 0.01, "wanted channel power {}", wanted);
+        assert!(adjacent < wanted * 1e-5, "adjacent channel power {}", adjacent);
+        // Tone is down-converted to 1 kHz on the first carrier
+        let rotation: Complex<f32> = outputs[0][200..].windows(2).map(|w| w[1] * w[0].conj()).sum();
+        let offset = rotation.arg() as f64 * modem::FS / (2.0 * std::f64::consts::PI);
+        assert!((offset - 1e3).abs() < 1.0, "frequency offset {}", offset);
+    }
+
+    #[test]
+    fn test_rx_modes() {
+        let conf = L1Config::parse("
+            [radio]
+            rx_freq = 400e6
+            tx_freq = 390e6
+            duplex_spacing = -10e6
+            [carrier]
+            freq = 390.025e6
+            role = ms
+            [carrier]
+            freq = 390.05e6
+            role = traffic
+            [carrier]
+            freq = 400.0125e6
+            direction = rx
+            role = dmo
+        ").unwrap();
+        let dsp = L1Dsp::new(&conf).unwrap();
+        let modes: Vec<RxMode> = dsp.rx_carriers.iter().map(|c| c.detector.mode()).collect();
+        assert!(modes == [RxMode::Downlink, RxMode::Uplink, RxMode::Dmo]);
+        assert_eq!(dsp.rx_carriers[0].channel, 2);
+    }
+
+    #[test]
+    fn test_add_remove_carrier() {
+        let mut dsp = L1Dsp::new(&test_config(vec![
+            CarrierConfig { freq: 400.025e6, direction: CarrierDirection::Both, role: CarrierRole::MainControl },
+        ])).unwrap();
+        let traffic = CarrierConfig { freq: 400.05e6, direction: CarrierDirection::Both, role: CarrierRole::Traffic };
+        assert_eq!(dsp.add_carrier(&traffic), Ok(1));
+        assert_eq!(dsp.rx_carriers.len(), 2);
+        assert_eq!(dsp.tx_carriers.len(), 2);
+        assert!(dsp.remove_carrier(1));
+        assert!(!dsp.remove_carrier(1));
+        assert_eq!(dsp.tx_carriers.iter().map(|c| c.id).collect::<Vec<i32>>(), vec![0]);
+        // Indexes of removed carriers are not reused
+        assert_eq!(dsp.add_carrier(&traffic), Ok(2));
+        // Invalid carrier is not added and does not consume an index
+        assert!(dsp.add_carrier(&CarrierConfig { freq: 401e6, ..traffic }).is_err());
+        assert_eq!(dsp.add_carrier(&traffic), Ok(3));
+    }
+
+    /// State for callbacks of the loopback test.
+    #[derive(Default)]
+    struct LoopbackTest {
+        tx_bits: Vec<[u8; 470]>,
+        rx_bits: Vec<[u8; 470]>,
+        rx_other: usize,
+    }
+
+    extern "C" fn loopback_tx_burst(arg: *mut std::ffi::c_void, _carrier: i32, _slot: SlotNumber, _slot_time: i64, burst: *mut TxBurst) {
+        let test = unsafe { &mut *(arg as *mut LoopbackTest) };
+        let mut bits = [0u8; 470];
+        // Pseudo-random data with normal training sequence 1
+        let mut state: u32 = 12345 + test.tx_bits.len() as u32;
+        for b in bits.iter_mut() {
+            state = state.wrapping_mul(1103515245).wrapping_add(12345);
+            *b = (state >> 16 & 1) as u8;
+        }
+        bits[230..252].copy_from_slice(&[1,1, 0,1, 0,0, 0,0, 1,1, 1,0, 1,0, 0,1, 1,1, 0,1, 0,0]);
+        test.tx_bits.push(bits);
+        unsafe { *burst = TxBurst::Dmo(bits); }
+    }
+
+    extern "C" fn loopback_rx_burst(arg: *mut std::ffi::c_void, _carrier: i32, _slot: SlotNumber, _slot_time: i64, burst: *const RxBurst) {
+        let test = unsafe { &mut *(arg as *mut LoopbackTest) };
+        match unsafe { &*burst } {
+            RxBurst::None => {},
+            RxBurst::DmoNormal1(b) => test.rx_bits.push(b.bits),
+            _ => test.rx_other += 1,
+        }
+    }
+
+    extern "C" fn loopback_rx_cmd(_arg: *mut std::ffi::c_void, _carrier: i32, _commands: *mut L1RxCommands) {}
+    extern "C" fn loopback_tx_cmd(_arg: *mut std::ffi::c_void, _carrier: i32, _commands: *mut L1TxCommands) {}
+
+    /// Transmit direct mode bursts through the whole signal processing
+    /// chain and a simulated radio, returning what was transmitted
+    /// and received.
+    /// Callbacks passing bursts to and from a LoopbackTest.
+    fn loopback_callbacks(test: &mut LoopbackTest) -> L1Callbacks {
+        let arg = test as *mut LoopbackTest as *mut std::ffi::c_void;
+        L1Callbacks {
+            rx_burst: loopback_rx_burst,
+            rx_burst_arg: arg,
+            tx_burst: loopback_tx_burst,
+            tx_burst_arg: arg,
+            rx_cmd: loopback_rx_cmd,
+            rx_cmd_arg: arg,
+            tx_cmd: loopback_tx_cmd,
+            tx_cmd_arg: arg,
+        }
+    }
+
+    fn run_loopback(fs: f64, backend: crate::config::Backend, impairments: crate::io::channel::Impairments) -> LoopbackTest {
+        let mut conf = L1Config {
+            fs: fs,
+            rx_freq: 400e6,
+            tx_freq: 400e6,
+            carriers: vec![
+                CarrierConfig { freq: 400.0125e6, direction: CarrierDirection::Both, role: CarrierRole::Dmo },
+            ],
+            backend: backend,
+            ..L1Config::default()
+        };
+        conf.loopback.stop_time = 200_000_000;
+        conf.channel.stop_time = 200_000_000;
+        conf.channel.impairments = impairments;
+        let mut dsp = L1Dsp::new(&conf).unwrap();
+        let mut radio = conf.open_radio().unwrap();
+        let mut test = LoopbackTest::default();
+        let callbacks = loopback_callbacks(&mut test);
+        while radio.process(|rx_buf, rx_time, tx_buf, tx_time| {
+            dsp.process(rx_buf, rx_time, tx_buf, tx_time, &callbacks)
+        }).is_ok() {}
+        test
+    }
+
+    /// Check that transmitted bursts were received correctly.
+    /// max_other is the number of other detected bursts allowed.
+    fn check_loopback(test: &LoopbackTest, max_other: usize) {
+        // 200 ms is about 14 slots. Transmission starts in the middle
+        // of the first slot, so the first burst is only partially
+        // transmitted. Bursts transmitted near the end are still
+        // in flight, so they are not received either.
+        assert!(test.tx_bits.len() >= 14);
+        assert!(test.rx_bits.len() >= test.tx_bits.len() - 3);
+        assert!(test.rx_other <= max_other);
+        for (tx, rx) in test.tx_bits[1..].iter().zip(test.rx_bits.iter()) {
+            assert_eq!(tx[..], rx[..]);
+        }
+    }
+
+    #[test]
+    fn test_different_block_lengths() {
+        let conf = L1Config {
+            fs: 1.8e6,
+            rx_freq: 400e6,
+            tx_freq: 400e6,
+            carriers: vec![
+                CarrierConfig { freq: 400.0125e6, direction: CarrierDirection::Both, role: CarrierRole::Dmo },
+            ],
+            ..L1Config::default()
+        };
+        let mut dsp = L1Dsp::new(&conf).unwrap();
+        let mut test = LoopbackTest::default();
+        let callbacks = loopback_callbacks(&mut test);
+        let cic_factor = dsp.common.cic_factor;
+        let rx_buf = vec![num::zero(); cic_factor * 2];
+        // Transmit block is longer and not a multiple of CIC ratio,
+        // so the remainder shall be filled with zeros.
+        let mut tx_buf = vec![Complex::new(1.0, 1.0); cic_factor * 3 + 1];
+        dsp.process(&rx_buf, 0, &mut tx_buf, 1_000_000, &callbacks);
+        assert_eq!(tx_buf[cic_factor * 3], num::zero());
+        assert_eq!(test.tx_bits.len(), 1);
+    }
+
+    #[test]
+    fn test_loopback() {
+        check_loopback(&run_loopback(1.8e6, crate::config::Backend::Loopback, Default::default()), 0);
+    }
+
+    #[test]
+    fn test_resampled_loopback() {
+        // Sample rates that are not a multiple of modem sample rate
+        for fs in [2e6, 3.84e6, 5e6] {
+            check_loopback(&run_loopback(fs, crate::config::Backend::Loopback, Default::default()), 0);
+        }
+    }
+
+    #[test]
+    fn test_channel_impairments() {
+        check_loopback(&run_loopback(1.8e6, crate::config::Backend::Channel, crate::io::channel::Impairments {
+            gain: -3.0,
+            noise: -40.0,
+            freq_offset: 300.0,
+            drift: 5.0,
+            iq_amplitude: 0.5,
+            iq_phase: 2.0,
+            ..Default::default()
+        }), 1); // A training sequence may be falsely detected in noise before the first burst
+    }
+
+    #[test]
+    fn test_invalid_carrier_frequencies() {
+        // Not on channel raster
+        assert!(L1Dsp::new(&test_config(vec![
+            CarrierConfig { freq: 400.001e6, direction: CarrierDirection::Tx, role: CarrierRole::Traffic },
+        ])).is_err());
+        // Outside band
+        assert!(L1Dsp::new(&test_config(vec![
+            CarrierConfig { freq: 401e6, direction: CarrierDirection::Tx, role: CarrierRole::Traffic },
+        ])).is_err());
+        // Receive frequency outside band due to missing duplex spacing
+        assert!(L1Dsp::new(&test_config(vec![
+            CarrierConfig { freq: 400e6, direction: CarrierDirection::Both, role: CarrierRole::Dmo },
+        ])).is_err());
+    }
+
+    #[test]
+    fn test_invalid_sample_rates() {
+        let carriers = vec![
+            CarrierConfig { freq: 400.025e6, direction: CarrierDirection::Tx, role: CarrierRole::Traffic },
+        ];
+        for fs in [100e3, 50e6, 1.8000005e6, 1.800001e6] {
+            let conf = L1Config { fs: fs, ..test_config(carriers.clone()) };
+            assert!(L1Dsp::new(&conf).is_err(), "fs {}", fs);
+        }
+        // Block length not a multiple of CIC factor
+        let conf = L1Config { blocklen: Some(7201), ..test_config(carriers.clone()) };
+        assert!(L1Dsp::new(&conf).is_err());
+        // Sample rates that need a resampler are fine
+        for fs in [2e6, 3.84e6, 5e6] {
+            let conf = L1Config { fs: fs, ..test_config(carriers.clone()) };
+            assert!(L1Dsp::new(&conf).is_ok(), "fs {}", fs);
+        }
+    }
+
+    #[test]
+    fn test_frequency_plan() {
+        let dsp = L1Dsp::new(&L1Config { fs: 2e6, ..test_config(vec![
+            CarrierConfig { freq: 400.025e6, direction: CarrierDirection::Both, role: CarrierRole::MainControl },
+        ]) }).unwrap();
+        assert_eq!(dsp.frequency_plan(), "\
+            Sample rate 2000000 Hz, CIC factor 28, CIC output rate 71428.571 Hz, resampled to 72000 Hz\n\
+            Sine table of 160 entries, 1 per 12500 Hz channel\n\
+            RX carrier 0: 390025000 Hz, channel +2 (+25000 Hz from center frequency 390000000 Hz), uplink bursts\n\
+            TX carrier 0: 400025000 Hz, channel +2 (+25000 Hz from center frequency 400000000 Hz)");
     }
 }
@@ -1,7 +1,9 @@
 use num;
 use num::Complex;
 use crate::slot::SlotNumber;
-use crate::burst::TxBurst;
+use crate::burst::{TxBurst, TxSubslotBurst};
+use crate::{L1RxCommands, L1TxCommands};
+use crate::timebase::Timebase;
 
 /// Symbol rate
 pub const SYMBOLRATE: f64 = 18000.0;
@@ -13,24 +15,158 @@ pub const SPS: usize = 4;
 pub const FS: f64 = SYMBOLRATE * (SPS as f64);
 
 
+/// Number of symbols in a timeslot
+pub const SLOT_SYMBOLS: usize = 255;
+
+/// Number of symbols in a subslot, rounded down.
+pub const SUBSLOT_SYMBOLS: usize = SLOT_SYMBOLS / 2;
+
+/// Number of symbols in guard period and power ramping
+/// before a discontinuous burst.
+pub const GUARD_SYMBOLS: usize = 17;
+
+/// Number of symbols used for power ramping
+/// before and after a discontinuous burst.
+const RAMP_SYMBOLS: usize = 3;
+
+/// Number of extra symbols included before and after a slot
+/// in demodulator output. This allows burst detection to search
+/// for a burst around the nominal slot timing.
+pub const SLOT_MARGIN: usize = 16;
+
+/// Number of symbols in demodulator output for each slot.
+pub const SLOT_WINDOW: usize = SLOT_SYMBOLS + 2 * SLOT_MARGIN;
+
 /// Length of a hyperframe in nanoseconds.
 const HYPERFRAME_NS: i64 = 1000_000 * 255*4*18*60 / 18;
 
+/// Length of a hyperframe in symbols.
+const HYPERFRAME_SYMBOLS: i32 = 255*4*18*60;
+
 fn ns_to_symbols(ns: i64) -> i32 {
     (ns * 9 / 500000) as i32
 }
 
-#[allow(dead_code)]
 fn symbols_to_ns(symbols: i32) -> i64 {
     (symbols as i64) * 500000 / 9
 }
 
+/// Find position within a hyperframe for a timestamp,
+/// given the timestamp at the beginning of a hyperframe.
+/// Returns a tuple (time from beginning of hyperframe,
+/// symbol number within hyperframe).
+fn hyperframe_position(htime: i64, time: i64) -> (i64, i32) {
+    let time_in_hyperframe = (time - htime).rem_euclid(HYPERFRAME_NS);
+    (time_in_hyperframe, ns_to_symbols(time_in_hyperframe))
+}
+
+/// Slot index within a hyperframe for a symbol number,
+/// which may be negative or past the end of the hyperframe.
+fn slot_index(hsym: i32) -> i32 {
+    hsym.rem_euclid(HYPERFRAME_SYMBOLS) / SLOT_SYMBOLS as i32
+}
+
+/// Compute timestamp at the beginning of a hyperframe
+/// from timestamp of a given slot.
+fn hyperframe_time(slot_time: i64, slot: SlotNumber) -> i64 {
+    slot_time - symbols_to_ns(slot.to_int() * SLOT_SYMBOLS as i32)
+}
+
+/// One symbol period in units of symbol clock phase.
+const SYMBOL_PHASE: u64 = 1 << 32;
+
+/// Symbol clock driven by a phase accumulator.
+///
+/// The clock is synchronized to the hyperframe structure from
+/// a timestamp, and after that it is advanced by counting samples,
+/// so that rounding or jitter of timestamps does not make the number
+/// of samples per symbol vary. Timestamps are only followed again
+/// if they jump by more than half a sample, such as after a gap
+/// in the signal.
+struct SymbolClock {
+    timebase: Timebase,
+    /// Phase increment per sample
+    step: u64,
+    /// Position within the current symbol
+    phase: u64,
+    /// Current symbol number within a hyperframe
+    hsym: i32,
+    /// Timestamp at the beginning of the current hyperframe
+    hyperframe_start: i64,
+    /// Timestamp of the sample the clock was synchronized at,
+    /// and number of samples since then.
+    /// None if not synchronized yet.
+    sync: Option<(i64, i64)>,
+}
+
+impl SymbolClock {
+    /// fs is the sample rate the clock is advanced at.
+    /// It does not need to be a multiple of symbol rate.
+    fn new(fs: f64) -> Self {
+        Self {
+            timebase: Timebase::new(fs),
+            step: (SYMBOLRATE / fs * SYMBOL_PHASE as f64).round() as u64,
+            phase: 0,
+            hsym: 0,
+            hyperframe_start: 0,
+            sync: None,
+        }
+    }
+
+    /// Synchronize the clock to a timestamp,
+    /// given the timestamp at the beginning of a hyperframe.
+    fn synchronize(&mut self, htime: i64, time: i64) {
+        // Position is taken half a sample later, so that
+        // each symbol begins at the sample nearest to its nominal start.
+        let t = time + self.timebase.samples_to_ns(1) / 2;
+        let time_in_hyperframe = (t - htime).rem_euclid(HYPERFRAME_NS);
+        self.hyperframe_start = t - time_in_hyperframe;
+        // Symbol rate is 9/500000 symbols per nanosecond
+        let symbols = time_in_hyperframe * 9;
+        self.hsym = (symbols / 500000) as i32;
+        self.phase = ((symbols % 500000) as u64 * SYMBOL_PHASE) / 500000;
+        self.sync = Some((time, 0));
+    }
+
+    /// Advance the clock by one sample.
+    /// Returns true if a new symbol begins at this sample.
+    fn tick(&mut self, htime: i64, time: i64) -> bool {
+        if let Some((sync_time, samples)) = self.sync {
+            let samples = samples + 1;
+            let expected = sync_time + self.timebase.samples_to_ns(samples);
+            if !self.timebase.is_discontinuous(time, expected) {
+                self.sync = Some((sync_time, samples));
+                self.phase += self.step;
+                if self.phase < SYMBOL_PHASE {
+                    return false;
+                }
+                self.phase -= SYMBOL_PHASE;
+                self.hsym += 1;
+                if self.hsym >= HYPERFRAME_SYMBOLS {
+                    self.hsym = 0;
+                    self.hyperframe_start += HYPERFRAME_NS;
+                }
+                return true;
+            }
+        }
+        // Not synchronized yet or timestamp jumped,
+        // so start from the symbol at this timestamp.
+        self.synchronize(htime, time);
+        true
+    }
+
+    /// Timestamp at the beginning of a symbol in the current hyperframe.
+    fn symbol_time(&self, hsym: i32) -> i64 {
+        self.hyperframe_start + symbols_to_ns(hsym)
+    }
+}
+
 pub struct Modulator {
     /// Timestamp at the beginning of a hyperframe
     /// is used as a reference point.
     htime: i64,
 
-    prev_hsym: i32,
+    clock: SymbolClock,
 
     mapper: DqpskMapper,
 
@@ -41,10 +177,11 @@ pub struct Modulator {
 }
 
 impl Modulator {
-    pub fn new() -> Self {
+    /// fs is the sample rate of modulator output.
+    pub fn new(fs: f64) -> Self {
         Self {
             htime: 0,
-            prev_hsym: 255,
+            clock: SymbolClock::new(fs),
             burst_slot: SlotNumber::new(4, 18, 60),
             burst: TxBurst::None,
             mapper: DqpskMapper::new(),
@@ -52,39 +189,276 @@ impl Modulator {
     }
 
     /// Produce a sample of transmit signal before matched filtering.
+    /// get_commands is called before beginning each slot.
     pub fn sample(
         &mut self,
         time: i64,
+        get_commands: &mut dyn FnMut(&mut L1TxCommands),
         get_burst: &mut dyn FnMut(SlotNumber, i64, &mut TxBurst),
     ) -> Complex<f32> {
-        let mut output: Complex<f32> = num::zero();
-        // Current symbol number within a hyperframe
-        let hsym = ns_to_symbols((time - self.htime).rem_euclid(HYPERFRAME_NS));
         // Is it time for a new symbol?
-        if hsym != self.prev_hsym {
-            // Split into a slot number and a symbol number within a slot.
-            let symnum = hsym.rem_euclid(255);
-            let slot = SlotNumber::from_int(hsym.div_euclid(255));
-            // Did a new slot just begin?
-            if slot != self.burst_slot {
-                self.burst_slot = slot;
-                self.burst = TxBurst::None;
-                // Ask for a new burst to transmit.
-                // TODO: pass slot time
-                get_burst(slot, 0, &mut self.burst);
+        if !self.clock.tick(self.htime, time) {
+            return num::zero();
+        }
+        // Split into a slot number and a symbol number within a slot.
+        let mut symnum = self.clock.hsym.rem_euclid(255);
+        let mut slot = SlotNumber::from_int(self.clock.hsym.div_euclid(255));
+        // Did a new slot just begin?
+        if slot != self.burst_slot {
+            let mut commands = L1TxCommands::default();
+            get_commands(&mut commands);
+            if commands.set_timing {
+                self.htime = hyperframe_time(commands.timing_time, commands.timing_slot);
+                self.clock.synchronize(self.htime, time);
+                symnum = self.clock.hsym.rem_euclid(255);
+                slot = SlotNumber::from_int(self.clock.hsym.div_euclid(255));
             }
-            output = match self.burst {
-                TxBurst::None => num::zero(),
-                TxBurst::Dl(bits) =>
-                    self.mapper.symbol(
-                        bits[symnum as usize * 2]     != 0,
-                        bits[symnum as usize * 2 + 1] != 0),
-                _ => todo!(),
-            };
+            self.burst_slot = slot;
+            self.burst = TxBurst::None;
+            // Ask for a new burst to transmit.
+            let slot_time = self.clock.symbol_time(self.clock.hsym - symnum);
+            get_burst(slot, slot_time, &mut self.burst);
+        }
+        match self.burst {
+            TxBurst::None => num::zero(),
+            TxBurst::Dl(ref bits) =>
+                self.mapper.symbol(
+                    bits[symnum as usize * 2]     != 0,
+                    bits[symnum as usize * 2 + 1] != 0),
+            TxBurst::Dmo(ref bits) =>
+                discontinuous_symbol(&mut self.mapper, bits, GUARD_SYMBOLS, symnum as usize),
+            TxBurst::UlNormal(ref bits) =>
+                discontinuous_symbol(&mut self.mapper, bits, GUARD_SYMBOLS, symnum as usize),
+            TxBurst::UlControl(ref subslots) => {
+                let subslot = (symnum as usize / SUBSLOT_SYMBOLS).min(1);
+                match subslots[subslot] {
+                    TxSubslotBurst::None => num::zero(),
+                    TxSubslotBurst::UlControl(ref bits) =>
+                        discontinuous_symbol(&mut self.mapper, bits,
+                            GUARD_SYMBOLS + subslot * SUBSLOT_SYMBOLS, symnum as usize),
+                }
+            },
         }
+    }
+}
+
+/// Produce a symbol of a discontinuous burst, including power ramping.
+/// first_symbol is the position of the first symbol of the burst
+/// and symnum the current symbol number within a slot.
+fn discontinuous_symbol(
+    mapper: &mut DqpskMapper,
+    bits: &[u8],
+    first_symbol: usize,
+    symnum: usize,
+) -> Complex<f32> {
+    let symbols = bits.len() / 2;
+    // Symbol number within the burst, including ramping period before it.
+    let k = match (symnum + RAMP_SYMBOLS).checked_sub(first_symbol) {
+        Some(k) if k < symbols + RAMP_SYMBOLS * 2 => k,
+        _ => return num::zero(),
+    };
+    if k < RAMP_SYMBOLS || k >= symbols + RAMP_SYMBOLS {
+        // Ramp up and down with a raised cosine shape.
+        // Transmit phase continuous dummy symbols during ramping
+        // so that the first symbol of the burst has a phase reference.
+        let r = if k < RAMP_SYMBOLS { k + 1 } else { symbols + RAMP_SYMBOLS * 2 - k };
+        let gain = (std::f32::consts::FRAC_PI_2 * r as f32 / (RAMP_SYMBOLS + 1) as f32).sin().powi(2);
+        mapper.symbol(false, false) * gain
+    } else {
+        let i = (k - RAMP_SYMBOLS) * 2;
+        mapper.symbol(bits[i] != 0, bits[i + 1] != 0)
+    }
+}
+
+
+/// Demodulated symbols of a slot, including a margin before and after it.
+pub struct DemodulatedSlot {
+    /// Slot number
+    pub slot: SlotNumber,
+    /// Timestamp at the beginning of the slot
+    pub time: i64,
+    /// Differentially detected symbols normalized by signal power.
+    /// First symbol of the slot is at index SLOT_MARGIN.
+    pub symbols: [Complex<f32>; SLOT_WINDOW],
+    /// Signal power at each symbol sampling instant.
+    pub power: [f32; SLOT_WINDOW],
+    /// Timestamp of each symbol sampling instant.
+    pub times: [i64; SLOT_WINDOW],
+}
+
+impl DemodulatedSlot {
+    /// Make hard decisions of bits starting from a given symbol index.
+    /// Two bits are written for each symbol.
+    pub fn hard_bits(&self, first_symbol: usize, bits: &mut [u8]) {
+        for (b, s) in bits.chunks_exact_mut(2).zip(&self.symbols[first_symbol..]) {
+            b[0] = (s.im < 0.0) as u8;
+            b[1] = (s.re < 0.0) as u8;
+        }
+    }
+}
+
+/// Coefficient for averaging signal power in symbol timing recovery.
+const TIMING_AVERAGING: f32 = 0.01;
 
+/// How much more power another sampling phase needs to have
+/// before symbol timing switches to it.
+/// Without hysteresis, timing could alternate between two phases
+/// of nearly equal power if the optimum sampling instant is halfway
+/// between them, and every switch would skip or repeat a symbol.
+const TIMING_HYSTERESIS: f32 = 1.1;
+
+/// Length of symbol history buffer in demodulator.
+/// Hyperframe length shall be a multiple of this.
+const HISTORY_LEN: usize = SLOT_SYMBOLS * 2;
+
+pub struct Demodulator {
+    /// Timestamp at the beginning of a hyperframe
+    /// is used as a reference point.
+    htime: i64,
+
+    /// Symbol number of the previous symbol within a hyperframe.
+    /// Slot boundaries are detected by comparing it to the current one,
+    /// so that they are not missed if timestamps jump over them.
+    prev_hsym: i32,
+
+    /// Sample counter within a symbol period (0 to SPS-1).
+    sample_counter: usize,
+    /// Average signal power at each sampling phase.
+    /// The phase with highest power is used as the symbol sampling
+    /// instant, which is optimal for a raised cosine pulse.
+    phase_power: [f32; SPS],
+    /// Sampling phase currently used for symbols.
+    symbol_phase: usize,
+    /// Previous symbol sample used for differential detection.
+    prev_symbol: Complex<f32>,
+
+    /// Differentially detected symbols of recent slots,
+    /// indexed by symbol number within a hyperframe modulo HISTORY_LEN.
+    history: [Complex<f32>; HISTORY_LEN],
+    /// Signal power of recent symbols, indexed the same way.
+    power_history: [f32; HISTORY_LEN],
+    /// Timestamps of recent symbols, indexed the same way.
+    time_history: [i64; HISTORY_LEN],
+    /// Output is collected here to avoid allocating it for every slot.
+    output: DemodulatedSlot,
+}
+
+impl Demodulator {
+    pub fn new() -> Self {
+        Self {
+            htime: 0,
+            // Make the first symbol begin a new slot.
+            prev_hsym: -1,
+            sample_counter: 0,
+            phase_power: [0.0; SPS],
+            symbol_phase: 0,
+            prev_symbol: num::zero(),
+            history: [num::zero(); HISTORY_LEN],
+            power_history: [0.0; HISTORY_LEN],
+            time_history: [0; HISTORY_LEN],
+            output: DemodulatedSlot {
+                slot: SlotNumber::new(1, 1, 1),
+                time: 0,
+                symbols: [num::zero(); SLOT_WINDOW],
+                power: [0.0; SLOT_WINDOW],
+                times: [0; SLOT_WINDOW],
+            },
+        }
+    }
+
+    /// Process a sample of channel filtered received signal.
+    /// get_commands is called at the beginning of each slot.
+    /// slot_done is called after all symbols of a slot,
+    /// including the margin after it, have been demodulated.
+    pub fn sample(
+        &mut self,
+        time: i64,
+        in_: Complex<f32>,
+        get_commands: &mut dyn FnMut(&mut L1RxCommands),
+        slot_done: &mut dyn FnMut(&DemodulatedSlot),
+    ) {
+        let p = &mut self.phase_power[self.sample_counter];
+        *p += (in_.norm_sqr() - *p) * TIMING_AVERAGING;
+
+        let mut best_phase = 0;
+        for phase in 1..SPS {
+            if self.phase_power[phase] > self.phase_power[best_phase] {
+                best_phase = phase;
+            }
+        }
+        if self.phase_power[best_phase] > self.phase_power[self.symbol_phase] * TIMING_HYSTERESIS {
+            self.symbol_phase = best_phase;
+        }
+        let is_symbol = self.sample_counter == self.symbol_phase;
+        self.sample_counter = (self.sample_counter + 1) % SPS;
+        if !is_symbol {
+            return;
+        }
+
+        let power = self.phase_power[self.symbol_phase];
+        let symbol = if power > 0.0 {
+            in_ * self.prev_symbol.conj() / power
+        } else {
+            num::zero()
+        };
+        self.prev_symbol = in_;
+
+        // Symbol number within a hyperframe according to timestamp.
+        let (mut time_in_hyperframe, mut measured_hsym) = hyperframe_position(self.htime, time);
+        // Sampling instant may be near a symbol boundary, in which case
+        // timestamp jitter would make symbols alternate between being
+        // counted for the previous or next symbol number.
+        // Avoid this by counting symbols and only following the timestamp
+        // if it differs by more than one symbol.
+        let expected_hsym = (self.prev_hsym + 1).rem_euclid(HYPERFRAME_SYMBOLS);
+        let diff = (measured_hsym - expected_hsym + HYPERFRAME_SYMBOLS / 2)
+            .rem_euclid(HYPERFRAME_SYMBOLS) - HYPERFRAME_SYMBOLS / 2;
+        let mut hsym = if diff.abs() <= 1 { expected_hsym } else { measured_hsym };
+
+        if slot_index(hsym) != slot_index(self.prev_hsym) {
+            let mut commands = L1RxCommands::default();
+            get_commands(&mut commands);
+            if commands.set_timing {
+                self.htime = hyperframe_time(commands.timing_time, commands.timing_slot);
+                (time_in_hyperframe, measured_hsym) = hyperframe_position(self.htime, time);
+                hsym = measured_hsym;
+                // Symbols in history were numbered with the old timing
+                // and would end up in wrong slots.
+                self.history = [num::zero(); HISTORY_LEN];
+                self.power_history = [0.0; HISTORY_LEN];
+                self.time_history = [0; HISTORY_LEN];
+                self.prev_hsym = hsym - 1;
+            }
+        }
+
+        let h = hsym as usize % HISTORY_LEN;
+        self.history[h] = symbol;
+        self.power_history[h] = in_.norm_sqr();
+        self.time_history[h] = time;
+        // Has a slot and the margin after it been completed?
+        let margin = SLOT_MARGIN as i32;
+        if slot_index(hsym - margin) != slot_index(self.prev_hsym - margin) {
+            let start = ((slot_index(hsym - margin) - 1) * SLOT_SYMBOLS as i32).rem_euclid(HYPERFRAME_SYMBOLS);
+            self.output.slot = SlotNumber::from_int(start / SLOT_SYMBOLS as i32);
+            self.output.time = time - time_in_hyperframe + symbols_to_ns(start)
+                - if start > measured_hsym { HYPERFRAME_NS } else { 0 };
+            for i in 0..SLOT_WINDOW {
+                let h = (start - SLOT_MARGIN as i32 + i as i32).rem_euclid(HYPERFRAME_SYMBOLS) as usize % HISTORY_LEN;
+                self.output.symbols[i] = self.history[h];
+                self.output.power[i] = self.power_history[h];
+                self.output.times[i] = self.time_history[h];
+            }
+            slot_done(&self.output);
+        }
         self.prev_hsym = hsym;
-        output
+    }
+
+    /// Skip over missing samples in received signal,
+    /// keeping the symbol sampling phase aligned.
+    /// Symbol numbering follows timestamps, so it is
+    /// resynchronized by the next sample.
+    pub fn skip(&mut self, samples: i64) {
+        self.sample_counter = (self.sample_counter as i64 + samples).rem_euclid(SPS as i64) as usize;
     }
 }
 
@@ -127,3 +501,244 @@ impl DqpskMapper {
         CONSTELLATION[self.phase as usize]
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dsp::fir;
+
+    /// Modulate a burst and return magnitudes of symbols in the slot.
+    fn burst_magnitudes(burst: TxBurst) -> Vec<f32> {
+        let mut modulator = Modulator::new(FS);
+        let mut burst = Some(burst);
+        (0..SLOT_SYMBOLS as i32).map(|symbol| {
+            // Sample in the middle of each symbol period.
+            let time = symbols_to_ns(symbol) + symbols_to_ns(1) / 2;
+            modulator.sample(time, &mut |_| {}, &mut |_slot: SlotNumber, _slot_time: i64, b: &mut TxBurst| {
+                *b = burst.take().unwrap();
+            }).norm()
+        }).collect()
+    }
+
+    /// Check that a discontinuous burst is placed correctly in a slot,
+    /// with power ramping before and after it.
+    fn check_placement(magnitudes: &[f32], first_symbol: usize, symbols: usize) {
+        for (i, m) in magnitudes.iter().enumerate() {
+            if i + RAMP_SYMBOLS < first_symbol || i >= first_symbol + symbols + RAMP_SYMBOLS {
+                assert!(*m == 0.0);
+            } else if i < first_symbol || i >= first_symbol + symbols {
+                assert!(*m > 0.0 && *m < 1.0);
+            } else {
+                assert!((*m - 1.0).abs() < 1e-6);
+            }
+        }
+    }
+
+    #[test]
+    fn test_slot_time() {
+        let mut modulator = Modulator::new(FS);
+        // Start from the middle of a hyperframe to also test
+        // that the hyperframe number is taken into account.
+        let start = HYPERFRAME_NS * 3 + symbols_to_ns(HYPERFRAME_SYMBOLS / 2 + 10);
+        let mut slot_times: Vec<(SlotNumber, i64)> = Vec::new();
+        for i in 0..(SLOT_SYMBOLS * SPS * 3) as i64 {
+            let time = start + i * 1000_000_000 / FS as i64;
+            modulator.sample(time, &mut |_| {}, &mut |slot: SlotNumber, slot_time: i64, _burst: &mut TxBurst| {
+                slot_times.push((slot, slot_time));
+            });
+        }
+        assert_eq!(slot_times.len(), 4);
+        for (slot, slot_time) in &slot_times[1..] {
+            assert_eq!(*slot_time, HYPERFRAME_NS * 3 + symbols_to_ns(slot.to_int() * SLOT_SYMBOLS as i32));
+        }
+    }
+
+    /// Run modulator with a continuous burst for a number of samples
+    /// at given timestamps, returning the sample indexes where
+    /// a new symbol began and the slot timestamps.
+    fn symbol_starts(fs: f64, times: impl Iterator<Item = i64>) -> (Vec<usize>, Vec<i64>) {
+        let mut modulator = Modulator::new(fs);
+        let mut starts = Vec::new();
+        let mut slot_times = Vec::new();
+        for (i, time) in times.enumerate() {
+            let out = modulator.sample(time, &mut |_| {}, &mut |_slot: SlotNumber, slot_time: i64, b: &mut TxBurst| {
+                slot_times.push(slot_time);
+                *b = TxBurst::Dl([0; 510]);
+            });
+            if out != num::zero() {
+                starts.push(i);
+            }
+        }
+        (starts, slot_times)
+    }
+
+    #[test]
+    fn test_symbol_clock() {
+        // Timestamps with up to 3 us of jitter shall not
+        // change the number of samples per symbol.
+        let mut state: u32 = 1;
+        let (starts, _) = symbol_starts(FS, (0..SLOT_SYMBOLS as i64 * 8).map(|i| {
+            state = state.wrapping_mul(1103515245).wrapping_add(12345);
+            i * 1_000_000_000 / 72000 + (state >> 16) as i64 % 6000 - 3000
+        }));
+        assert!(starts.windows(2).all(|w| w[1] - w[0] == SPS));
+
+        // Sample rate that is not a multiple of symbol rate
+        let fs = 2e6 / 28.0;
+        let timebase = Timebase::new(fs);
+        let samples = (fs * 0.5) as i64;
+        let (starts, slot_times) = symbol_starts(fs, (0..samples).map(|i| timebase.samples_to_ns(i)));
+        assert_eq!(starts.len(), 9000);
+        assert!(starts.windows(2).all(|w| w[1] - w[0] == 3 || w[1] - w[0] == 4));
+        for (n, slot_time) in slot_times.iter().enumerate() {
+            assert_eq!(*slot_time, symbols_to_ns(n as i32 * SLOT_SYMBOLS as i32));
+        }
+    }
+
+    #[test]
+    fn test_tx_timing_command() {
+        let mut modulator = Modulator::new(FS);
+        let mut slots: Vec<(SlotNumber, i64)> = Vec::new();
+        let commanded_slot = SlotNumber::new(2, 3, 4);
+        let commanded_time = symbols_to_ns(100);
+        for i in 0..(SLOT_SYMBOLS * SPS * 3) as i64 {
+            let time = i * 1000_000_000 / FS as i64;
+            modulator.sample(time,
+                &mut |commands: &mut L1TxCommands| {
+                    if time == 0 {
+                        commands.set_timing = true;
+                        commands.timing_slot = commanded_slot;
+                        commands.timing_time = commanded_time;
+                    }
+                },
+                &mut |slot: SlotNumber, slot_time: i64, _burst: &mut TxBurst| {
+                    slots.push((slot, slot_time));
+                }
+            );
+        }
+        // Time 0 is in the slot before the commanded one.
+        assert!(slots[0].0 == commanded_slot.minus(1));
+        assert!(slots[1].0 == commanded_slot);
+        assert_eq!(slots[1].1, commanded_time);
+        assert!(slots[2].0 == commanded_slot.plus(1));
+    }
+
+    #[test]
+    fn test_rx_timestamp_jump() {
+        // Timestamps jump over the beginning of slot 1
+        // and the end of the margin after slot 0.
+        let mut demodulator = Demodulator::new();
+        let jump_at = ((SLOT_SYMBOLS - 10) * SPS) as i64;
+        let mut commands_called = 0;
+        let mut slots: Vec<SlotNumber> = Vec::new();
+        for i in 0..(SLOT_SYMBOLS * SPS * 3) as i64 {
+            let time = i * 1000_000_000 / FS as i64
+                + if i >= jump_at { symbols_to_ns(40) } else { 0 };
+            demodulator.sample(time, Complex::new(1.0, 0.0),
+                &mut |_| { commands_called += 1; },
+                &mut |demodulated: &DemodulatedSlot| { slots.push(demodulated.slot); });
+        }
+        assert_eq!(commands_called, 4);
+        // The first one is the last slot of the previous hyperframe,
+        // which ends in the beginning of the signal.
+        let slots: Vec<i32> = slots.iter().map(|slot| slot.to_int()).collect();
+        assert_eq!(slots, [HYPERFRAME_SYMBOLS / SLOT_SYMBOLS as i32 - 1, 0, 1, 2]);
+    }
+
+    #[test]
+    fn test_rx_timing_command() {
+        let mut demodulator = Demodulator::new();
+        let mut commands_called = 0;
+        let mut timing_set_at = None;
+        let mut slot_times: Vec<[i64; SLOT_WINDOW]> = Vec::new();
+        for i in 0..(SLOT_SYMBOLS * SPS * 3) as i64 {
+            let time = i * 1000_000_000 / FS as i64;
+            demodulator.sample(time, Complex::new(1.0, 0.0),
+                &mut |commands: &mut L1RxCommands| {
+                    commands_called += 1;
+                    if commands_called == 2 {
+                        // Move slot boundaries by 100 symbols.
+                        commands.set_timing = true;
+                        commands.timing_slot = SlotNumber::from_int(0);
+                        commands.timing_time = time + symbols_to_ns(100);
+                        timing_set_at = Some(time);
+                    }
+                },
+                &mut |demodulated: &DemodulatedSlot| { slot_times.push(demodulated.times); });
+        }
+        // Slots completed after the timing change shall not contain
+        // symbols demodulated before it.
+        let timing_set_at = timing_set_at.unwrap();
+        let after: Vec<_> = slot_times.iter().filter(|times| times.iter().any(|&t| t > timing_set_at)).collect();
+        assert!(!after.is_empty());
+        for times in after {
+            assert!(times.iter().all(|&t| t == 0 || t >= timing_set_at));
+        }
+    }
+
+    #[test]
+    fn test_dmo_burst_placement() {
+        check_placement(&burst_magnitudes(TxBurst::Dmo([1; 470])), GUARD_SYMBOLS, 235);
+    }
+
+    #[test]
+    fn test_ul_burst_placement() {
+        check_placement(&burst_magnitudes(TxBurst::UlNormal([1; 462])), GUARD_SYMBOLS, 231);
+        check_placement(
+            &burst_magnitudes(TxBurst::UlControl([TxSubslotBurst::UlControl([1; 206]), TxSubslotBurst::None])),
+            GUARD_SYMBOLS, 103);
+        check_placement(
+            &burst_magnitudes(TxBurst::UlControl([TxSubslotBurst::None, TxSubslotBurst::UlControl([1; 206])])),
+            GUARD_SYMBOLS + SUBSLOT_SYMBOLS, 103);
+    }
+
+    #[test]
+    fn test_modem_loopback() {
+        // Modulate a few slots of pseudorandom bits, pass them through
+        // transmit and receive channel filters, and demodulate.
+        let taps = fir::convert_symmetric_real_taps(&crate::dsp::CHANNEL_FILTER_TAPS);
+        let mut tx_filter = fir::FirCf32Sym::new(taps.clone());
+        let mut rx_filter = fir::FirCf32Sym::new(taps.clone());
+        let mut modulator = Modulator::new(FS);
+        let mut demodulator = Demodulator::new();
+
+        let mut bits_sent: Vec<(SlotNumber, [u8; 510])> = Vec::new();
+        let mut slots_received: Vec<(SlotNumber, [u8; SLOT_WINDOW*2])> = Vec::new();
+        let mut rng: u32 = 1;
+        for i in 0..(SLOT_SYMBOLS * SPS * 8) as i64 {
+            let time = i * 1000_000_000 / FS as i64;
+            let tx = modulator.sample(time, &mut |_| {},
+                &mut |slot: SlotNumber, _slot_time: i64, burst: &mut TxBurst| {
+                    let mut bits = [0u8; 510];
+                    for b in bits.iter_mut() {
+                        rng = rng.wrapping_mul(1103515245).wrapping_add(12345);
+                        *b = (rng >> 30) as u8 & 1;
+                    }
+                    bits_sent.push((slot, bits));
+                    *burst = TxBurst::Dl(bits);
+                }
+            );
+            let rx = rx_filter.sample(tx_filter.sample(tx * SPS as f32));
+            demodulator.sample(time, rx, &mut |_| {}, &mut |demodulated: &DemodulatedSlot| {
+                let mut bits = [0u8; SLOT_WINDOW*2];
+                demodulated.hard_bits(0, &mut bits);
+                slots_received.push((demodulated.slot, bits));
+            });
+        }
+
+        // Filter delay is a few symbols, so the received slot should match
+        // transmitted slot at some offset within the margin.
+        // Skip first slots where timing recovery may not have settled yet.
+        let mut checked = 0;
+        for (slot, received) in slots_received.iter().skip(3) {
+            let (_, sent) = bits_sent.iter().find(|(s, _)| s == slot).unwrap();
+            let matches = (0..SLOT_MARGIN*2).any(|offset| {
+                // Skip first symbol, which depends on the symbol before the slot.
+                received[offset*2 + 2 .. offset*2 + 510] == sent[2..]
+            });
+            assert!(matches);
+            checked += 1;
+        }
+        assert!(checked >= 3);
+    }
+}
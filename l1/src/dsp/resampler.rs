@@ -0,0 +1,124 @@
+//! Fractional resampler for radio sample rates
+//! that are not an integer multiple of modem sample rate.
+//!
+//! Uses cubic Lagrange interpolation in a Farrow structure.
+//! Channel filter after the resampler in receive direction, and before
+//! it in transmit direction, limits the bandwidth of the signal,
+//! so a simple interpolator is enough.
+
+use num::Complex;
+
+/// One input sample period in units of resampler position.
+const ONE: u64 = 1 << 32;
+
+pub struct Resampler {
+    /// Input sample periods per output sample
+    step: u64,
+    /// Position of the next output sample,
+    /// counted from history[1] in input sample periods.
+    mu: u64,
+    /// Last 4 input samples, oldest first.
+    /// Output is interpolated between history[1] and history[2].
+    history: [Complex<f32>; 4],
+}
+
+impl Resampler {
+    pub fn new(fs_in: f64, fs_out: f64) -> Self {
+        Self {
+            step: (fs_in / fs_out * ONE as f64).round() as u64,
+            mu: ONE,
+            history: [num::zero(); 4],
+        }
+    }
+
+    fn shift_in(&mut self, x: Complex<f32>) {
+        self.history.copy_within(1.., 0);
+        self.history[3] = x;
+    }
+
+    fn interpolate(&self) -> Complex<f32> {
+        let [h0, h1, h2, h3] = self.history;
+        let mu = self.mu as f32 * (1.0 / ONE as f32);
+        let c1 = h0 * (-1.0 / 3.0) + h1 * -0.5 + h2 + h3 * (-1.0 / 6.0);
+        let c2 = (h0 + h2) * 0.5 - h1;
+        let c3 = (h3 - h0) * (1.0 / 6.0) + (h1 - h2) * 0.5;
+        ((c3 * mu + c2) * mu + c1) * mu + h1
+    }
+
+    /// Offset of the newest input sample from the next output sample
+    /// in input sample periods.
+    fn newest_offset(&self) -> f64 {
+        2.0 - self.mu as f64 / ONE as f64
+    }
+
+    /// Process one input sample.
+    /// output is called for each output sample produced,
+    /// with the offset of the input sample after it
+    /// in input sample periods.
+    pub fn push(&mut self, x: Complex<f32>, output: &mut dyn FnMut(Complex<f32>, f64)) {
+        self.shift_in(x);
+        self.mu -= ONE;
+        while self.mu < ONE {
+            output(self.interpolate(), self.newest_offset());
+            self.mu += self.step;
+        }
+    }
+
+    /// Produce one output sample.
+    /// input is called to get each input sample needed,
+    /// with the offset of the input sample after the output sample
+    /// in input sample periods.
+    pub fn pull(&mut self, input: &mut dyn FnMut(f64) -> Complex<f32>) -> Complex<f32> {
+        while self.mu >= ONE {
+            self.mu -= ONE;
+            let x = input(self.newest_offset());
+            self.shift_in(x);
+        }
+        let y = self.interpolate();
+        self.mu += self.step;
+        y
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Complex exponential at a given frequency and time.
+    fn tone(freq: f64, t: f64) -> Complex<f32> {
+        let phase = 2.0 * std::f64::consts::PI * freq * t;
+        Complex::new(phase.cos() as f32, phase.sin() as f32)
+    }
+
+    #[test]
+    fn test_resampler() {
+        let freq = 5000.0;
+        for (fs_in, fs_out) in [(2e6 / 28.0, 72000.0), (72000.0, 3.84e6 / 53.0)] {
+            // Output of push shall be the input signal sampled
+            // at the times given by the offsets.
+            let mut resampler = Resampler::new(fs_in, fs_out);
+            let mut outputs = 0;
+            for n in 0..10000 {
+                resampler.push(tone(freq, n as f64 / fs_in), &mut |y, offset| {
+                    let t = (n as f64 - offset) / fs_in;
+                    if n > 4 {
+                        assert!((y - tone(freq, t)).norm() < 1e-3);
+                    }
+                    outputs += 1;
+                });
+            }
+            assert!((outputs as f64 - 10000.0 * fs_out / fs_in).abs() <= 1.0);
+
+            // Same for pull, where the offsets give input times.
+            let mut resampler = Resampler::new(fs_in, fs_out);
+            let mut t_out = 0.0;
+            for m in 0..10000 {
+                let y = resampler.pull(&mut |offset| tone(freq, t_out + offset / fs_in));
+                if m > 4 {
+                    assert!((y - tone(freq, t_out)).norm() < 1e-3);
+                }
+                t_out = (m + 1) as f64 / fs_out;
+            }
+        }
+    }
+}
@@ -0,0 +1,186 @@
+//! Error type for L1.
+
+use std::ffi::{c_char, c_int};
+use std::fmt;
+
+/// Error codes returned by C functions.
+/// Success is 0 and errors are negative.
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum L1ErrorCode {
+    /// No error
+    Ok = 0,
+    /// Opening or setting up radio device failed
+    DeviceOpen = -1,
+    /// Receive overflow: some received samples were lost.
+    /// Processing can continue.
+    Overflow = -2,
+    /// Transmit underflow: transmit samples were late.
+    /// Processing can continue.
+    Underflow = -3,
+    /// Timeout waiting for received samples.
+    /// Processing can continue.
+    Timeout = -4,
+    /// Invalid configuration or argument
+    Config = -5,
+    /// File or network I/O error
+    Io = -6,
+    /// Other radio stream error
+    Stream = -7,
+    /// End of input signal or stop time was reached
+    EndOfStream = -8,
+    /// Radio reported corrupted received data.
+    /// Processing can continue.
+    Corruption = -9,
+    /// Radio reported a timestamp error.
+    /// Processing can continue.
+    TimeError = -10,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum L1Error {
+    /// Opening or setting up radio device failed
+    DeviceOpen(String),
+    /// Receive overflow
+    Overflow,
+    /// Transmit underflow
+    Underflow,
+    /// Timeout waiting for received samples
+    Timeout,
+    /// Invalid configuration or argument
+    Config(String),
+    /// File or network I/O error
+    Io(String),
+    /// Other radio stream error
+    Stream(String),
+    /// End of input signal or stop time was reached
+    EndOfStream,
+    /// Corrupted received data
+    Corruption,
+    /// Radio timestamp error
+    TimeError,
+}
+
+impl L1Error {
+    pub fn code(&self) -> L1ErrorCode {
+        match self {
+            L1Error::DeviceOpen(_) => L1ErrorCode::DeviceOpen,
+            L1Error::Overflow      => L1ErrorCode::Overflow,
+            L1Error::Underflow     => L1ErrorCode::Underflow,
+            L1Error::Timeout       => L1ErrorCode::Timeout,
+            L1Error::Config(_)     => L1ErrorCode::Config,
+            L1Error::Io(_)         => L1ErrorCode::Io,
+            L1Error::Stream(_)     => L1ErrorCode::Stream,
+            L1Error::EndOfStream   => L1ErrorCode::EndOfStream,
+            L1Error::Corruption    => L1ErrorCode::Corruption,
+            L1Error::TimeError     => L1ErrorCode::TimeError,
+        }
+    }
+
+    /// Whether processing can continue after the error.
+    pub fn is_recoverable(&self) -> bool {
+        self.code().is_recoverable()
+    }
+}
+
+impl L1ErrorCode {
+    /// Whether processing can continue after the error.
+    pub fn is_recoverable(self) -> bool {
+        matches!(self, L1ErrorCode::Overflow | L1ErrorCode::Underflow | L1ErrorCode::Timeout |
+            L1ErrorCode::Corruption | L1ErrorCode::TimeError)
+    }
+
+    /// Convert from a C error code.
+    pub fn from_c(code: c_int) -> Option<Self> {
+        [
+            L1ErrorCode::Ok,
+            L1ErrorCode::DeviceOpen,
+            L1ErrorCode::Overflow,
+            L1ErrorCode::Underflow,
+            L1ErrorCode::Timeout,
+            L1ErrorCode::Config,
+            L1ErrorCode::Io,
+            L1ErrorCode::Stream,
+            L1ErrorCode::EndOfStream,
+            L1ErrorCode::Corruption,
+            L1ErrorCode::TimeError,
+        ].into_iter().find(|c| *c as c_int == code)
+    }
+
+    /// Description of the error as a NUL terminated string.
+    fn description(self) -> &'static str {
+        match self {
+            L1ErrorCode::Ok          => "Success\0",
+            L1ErrorCode::DeviceOpen  => "Failed to open radio device\0",
+            L1ErrorCode::Overflow    => "Receive overflow\0",
+            L1ErrorCode::Underflow   => "Transmit underflow\0",
+            L1ErrorCode::Timeout     => "Timeout\0",
+            L1ErrorCode::Config      => "Invalid configuration\0",
+            L1ErrorCode::Io          => "I/O error\0",
+            L1ErrorCode::Stream      => "Radio stream error\0",
+            L1ErrorCode::EndOfStream => "End of stream\0",
+            L1ErrorCode::Corruption  => "Received data corrupted\0",
+            L1ErrorCode::TimeError   => "Radio timestamp error\0",
+        }
+    }
+}
+
+impl fmt::Display for L1Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let description = self.code().description().trim_end_matches('\0');
+        match self {
+            L1Error::DeviceOpen(msg) |
+            L1Error::Config(msg) |
+            L1Error::Io(msg) |
+            L1Error::Stream(msg) => write!(f, "{}: {}", description, msg),
+            _ => write!(f, "{}", description),
+        }
+    }
+}
+
+impl std::error::Error for L1Error {}
+
+impl From<L1Error> for c_int {
+    fn from(err: L1Error) -> c_int {
+        err.code() as c_int
+    }
+}
+
+/// Get a description of an error code returned by an L1 function.
+/// The returned string is statically allocated.
+#[no_mangle]
+pub extern "C" fn l1_strerror(code: c_int) -> *const c_char {
+    match L1ErrorCode::from_c(code) {
+        Some(code) => code.description(),
+        None => "Unknown error\0",
+    }.as_ptr() as *const c_char
+}
+
+/// Returns true if processing can continue after an error code
+/// returned by l1_process, such as after a momentary overflow.
+#[no_mangle]
+pub extern "C" fn l1_error_is_recoverable(code: c_int) -> bool {
+    L1ErrorCode::from_c(code).is_some_and(|code| code.is_recoverable())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::ffi::CStr;
+
+    #[test]
+    fn test_error_codes() {
+        let err = L1Error::Io("Failed to read RX file".to_string());
+        assert_eq!(c_int::from(err.clone()), -6);
+        assert_eq!(err.to_string(), "I/O error: Failed to read RX file");
+        assert!(L1Error::Overflow.is_recoverable());
+        assert!(!L1Error::EndOfStream.is_recoverable());
+        assert!(L1Error::Corruption.is_recoverable());
+        assert_eq!(c_int::from(L1Error::TimeError), -10);
+        assert!(l1_error_is_recoverable(L1ErrorCode::Timeout as c_int));
+        assert!(!l1_error_is_recoverable(-100));
+        let s = |code| unsafe { CStr::from_ptr(l1_strerror(code)) }.to_str().unwrap();
+        assert_eq!(s(-2), "Receive overflow");
+        assert_eq!(s(-100), "Unknown error");
+    }
+}
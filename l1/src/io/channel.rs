@@ -0,0 +1,493 @@
+//! Radio channel simulator.
+//!
+//! Works like the loopback backend, connecting transmit signal
+//! to receive signal, but applies channel impairments to the signal.
+//! This is useful for testing receiver sensitivity and robustness.
+//!
+//! Impairments are applied in the following order:
+//! multipath fading, gain, carrier frequency offset,
+//! sample clock drift, noise and IQ imbalance.
+
+use std::collections::VecDeque;
+use num::Complex;
+use super::loopback::{LoopbackIo, LoopbackIoConfig};
+use crate::L1Error;
+
+type StreamType = Complex<f32>;
+
+/// Multipath fading propagation model.
+/// Models follow the TETRA propagation models
+/// which each have two Rayleigh fading taps
+/// with classical Doppler spectrum.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum FadingProfile {
+    /// No fading
+    None,
+    /// Typical urban
+    TypicalUrban,
+    /// Bad urban
+    BadUrban,
+    /// Hilly terrain
+    HillyTerrain,
+}
+
+impl FadingProfile {
+    /// Delay (s) and power (dB) of each tap.
+    fn taps(self) -> &'static [(f64, f64)] {
+        match self {
+            FadingProfile::None => &[],
+            FadingProfile::TypicalUrban => &[(0.0, 0.0), (5e-6, -22.3)],
+            FadingProfile::BadUrban     => &[(0.0, 0.0), (5e-6, -3.0)],
+            FadingProfile::HillyTerrain => &[(0.0, 0.0), (15e-6, -8.6)],
+        }
+    }
+}
+
+/// Channel impairments.
+/// Default value has no impairments.
+#[derive(Copy, Clone, Debug)]
+pub struct Impairments {
+    /// Gain applied to the signal (dB)
+    pub gain: f64,
+    /// Power of additive white Gaussian noise
+    /// relative to full scale (dBFS).
+    /// Use negative infinity for no noise.
+    pub noise: f64,
+    /// Carrier frequency offset (Hz)
+    pub freq_offset: f64,
+    /// Receiver sample clock error (ppm).
+    /// Positive value makes the receiver sample faster.
+    pub drift: f64,
+    /// Multipath fading model
+    pub fading: FadingProfile,
+    /// Speed of the mobile for fading Doppler spread (km/h)
+    pub speed: f64,
+    /// Receiver IQ amplitude imbalance (dB)
+    pub iq_amplitude: f64,
+    /// Receiver IQ phase imbalance (degrees)
+    pub iq_phase: f64,
+    /// Seed for random number generator.
+    /// The same seed gives the same noise and fading.
+    pub seed: u64,
+}
+
+impl Default for Impairments {
+    fn default() -> Self {
+        Self {
+            gain: 0.0,
+            noise: f64::NEG_INFINITY,
+            freq_offset: 0.0,
+            drift: 0.0,
+            fading: FadingProfile::None,
+            speed: 0.0,
+            iq_amplitude: 0.0,
+            iq_phase: 0.0,
+            seed: 1,
+        }
+    }
+}
+
+pub struct ChannelIoConfig {
+    /// Processing block length in samples
+    pub blocklen: usize,
+    /// RX-TX round-trip latency as a multiple of processing block length.
+    pub latency_blocks: usize,
+    /// Sample rate
+    pub fs: f64,
+    /// Center frequency, used for computing Doppler spread
+    pub freq: f64,
+    /// Stop after this number of nanoseconds
+    pub stop_time: i64,
+    /// Impairments applied to the signal
+    pub impairments: Impairments,
+}
+
+/// Simple pseudo-random number generator (xorshift64*).
+/// Good enough for simulation and keeps results reproducible.
+struct Rng(u64);
+
+impl Rng {
+    fn new(seed: u64) -> Self {
+        // State shall not be zero
+        Self(seed.wrapping_mul(0x9E3779B97F4A7C15) | 1)
+    }
+
+    /// Uniformly distributed number in range [0, 1).
+    fn uniform(&mut self) -> f64 {
+        self.0 ^= self.0 >> 12;
+        self.0 ^= self.0 << 25;
+        self.0 ^= self.0 >> 27;
+        (self.0.wrapping_mul(0x2545F4914F6CDD1D) >> 11) as f64 * (1.0 / (1u64 << 53) as f64)
+    }
+
+    /// Complex Gaussian number with unit power.
+    fn complex_gaussian(&mut self) -> Complex<f64> {
+        let r = (-(1.0 - self.uniform()).ln()).sqrt();
+        Complex::from_polar(r, std::f64::consts::TAU * self.uniform())
+    }
+}
+
+/// Number of sinusoids in each Rayleigh fading generator
+const FADING_SINUSOIDS: usize = 16;
+/// Fading coefficients are updated once per this number of samples
+const FADING_UPDATE: usize = 32;
+
+/// Rayleigh fading generator using a sum of sinusoids
+/// with classical Doppler spectrum.
+struct RayleighFader {
+    /// Doppler frequency (radians per sample) and phase of each sinusoid
+    /// for in-phase and quadrature parts
+    sinusoids: Vec<(f64, f64, f64)>,
+    amplitude: f64,
+}
+
+impl RayleighFader {
+    fn new(rng: &mut Rng, max_doppler: f64, power: f64) -> Self {
+        let alpha = std::f64::consts::TAU * rng.uniform();
+        Self {
+            sinusoids: (0..FADING_SINUSOIDS).map(|n| {
+                let angle = (std::f64::consts::TAU * n as f64 + alpha) / FADING_SINUSOIDS as f64;
+                (
+                    max_doppler * angle.cos(),
+                    std::f64::consts::TAU * rng.uniform(),
+                    std::f64::consts::TAU * rng.uniform(),
+                )
+            }).collect(),
+            amplitude: (power / FADING_SINUSOIDS as f64).sqrt(),
+        }
+    }
+
+    /// Fading coefficient at a given sample index.
+    fn coefficient(&self, sample: u64) -> Complex<f64> {
+        let t = sample as f64;
+        let (re, im) = self.sinusoids.iter().fold((0.0, 0.0), |(re, im), (w, phi_i, phi_q)| {
+            (re + (w * t + phi_i).cos(), im + (w * t + phi_q).cos())
+        });
+        Complex::new(re, im) * self.amplitude
+    }
+}
+
+/// Multipath fading channel as a tapped delay line.
+struct Multipath {
+    /// Delay in samples and fading generator of each tap
+    taps: Vec<(usize, RayleighFader)>,
+    /// Current coefficient of each tap
+    coefficients: Vec<Complex<f64>>,
+    /// Previous input samples, newest first
+    history: VecDeque<Complex<f64>>,
+    sample: u64,
+}
+
+impl Multipath {
+    fn new(rng: &mut Rng, profile: FadingProfile, max_doppler: f64, fs: f64) -> Self {
+        let taps = profile.taps();
+        // Normalize total power of taps to 1
+        let total_power: f64 = taps.iter().map(|(_, p)| 10f64.powf(p / 10.0)).sum();
+        let taps: Vec<(usize, RayleighFader)> = taps.iter().map(|(delay, power)| (
+            (delay * fs).round() as usize,
+            RayleighFader::new(rng, max_doppler, 10f64.powf(power / 10.0) / total_power),
+        )).collect();
+        let max_delay = taps.iter().map(|(d, _)| *d).max().unwrap_or(0);
+        Self {
+            coefficients: vec![num::zero(); taps.len()],
+            taps,
+            history: vec![num::zero(); max_delay + 1].into(),
+            sample: 0,
+        }
+    }
+
+    fn process(&mut self, x: Complex<f64>) -> Complex<f64> {
+        if self.sample % FADING_UPDATE as u64 == 0 {
+            for (c, (_, fader)) in self.coefficients.iter_mut().zip(self.taps.iter()) {
+                *c = fader.coefficient(self.sample);
+            }
+        }
+        self.sample += 1;
+        self.history.pop_back();
+        self.history.push_front(x);
+        self.taps.iter().zip(self.coefficients.iter())
+            .map(|((delay, _), c)| self.history[*delay] * c)
+            .sum()
+    }
+}
+
+/// Resampler for simulating sample clock drift
+/// using cubic interpolation.
+struct DriftResampler {
+    /// Input samples not yet fully consumed
+    input: VecDeque<Complex<f64>>,
+    /// Position of next output sample relative to input[1]
+    position: f64,
+    /// Input samples consumed per output sample
+    ratio: f64,
+    /// Maximum number of buffered input samples
+    max_len: usize,
+}
+
+impl DriftResampler {
+    /// margin is the number of samples buffered in the beginning.
+    /// Drift can accumulate up to margin samples in either direction.
+    /// After that, a sample is repeated or dropped whenever drift
+    /// has accumulated another whole sample, which keeps the buffer
+    /// bounded at the cost of a timing slip.
+    fn new(drift_ppm: f64, margin: usize) -> Self {
+        Self {
+            // Start with some samples in the buffer, so that
+            // output does not run out of input when sampling faster.
+            input: vec![num::zero(); margin + 4].into(),
+            position: 0.0,
+            ratio: 1.0 + drift_ppm * 1e-6,
+            max_len: 2 * margin + 4,
+        }
+    }
+
+    /// Delay caused by the resampler in samples.
+    fn delay(margin: usize) -> usize {
+        margin + 3
+    }
+
+    fn push(&mut self, x: Complex<f64>) {
+        if self.input.len() >= self.max_len {
+            // Receiver is sampling slower and has used up the margin.
+            self.input.pop_front();
+        }
+        self.input.push_back(x);
+    }
+
+    /// Produce an output sample.
+    fn pop(&mut self) -> Complex<f64> {
+        let mu = self.position;
+        let (y0, y1, y2, y3) = (self.input[0], self.input[1], self.input[2], self.input[3]);
+        // Catmull-Rom cubic interpolation between y1 and y2
+        let out = y1 + (y2 - y0) * (0.5 * mu)
+            + (y0 * 2.0 - y1 * 5.0 + y2 * 4.0 - y3) * (0.5 * mu * mu)
+            + (y1 * 3.0 - y0 - y2 * 3.0 + y3) * (0.5 * mu * mu * mu);
+        self.position += self.ratio;
+        while self.position >= 1.0 {
+            self.position -= 1.0;
+            // If receiver is sampling faster and has used up the margin,
+            // keep the last samples, so they are interpolated again.
+            if self.input.len() > 4 {
+                self.input.pop_front();
+            }
+        }
+        out
+    }
+}
+
+/// State of the impairment simulation.
+struct Channel {
+    imp: Impairments,
+    rng: Rng,
+    multipath: Option<Multipath>,
+    drift: Option<DriftResampler>,
+    gain: f64,
+    noise_amplitude: f64,
+    /// Carrier phase (radians) and phase change per sample
+    phase: f64,
+    phase_step: f64,
+    /// Constants for IQ imbalance
+    iq_gain: f64,
+    iq_sin: f64,
+    iq_cos: f64,
+    /// Delay caused by simulation itself (ns), subtracted from timestamps
+    delay_time: i64,
+}
+
+impl Channel {
+    fn new(conf: &ChannelIoConfig) -> Self {
+        let imp = conf.impairments;
+        let mut rng = Rng::new(imp.seed);
+        let max_doppler = imp.speed / 3.6 * conf.freq / 299792458.0;
+        let iq_phase = imp.iq_phase.to_radians();
+        Self {
+            multipath: if imp.fading == FadingProfile::None { None } else {
+                Some(Multipath::new(&mut rng, imp.fading,
+                    std::f64::consts::TAU * max_doppler / conf.fs, conf.fs))
+            },
+            // Buffering one block lets realistic clock errors accumulate
+            // for a long time before the resampler has to slip a sample.
+            drift: if imp.drift == 0.0 { None } else {
+                Some(DriftResampler::new(imp.drift, conf.blocklen))
+            },
+            delay_time: if imp.drift == 0.0 { 0 } else {
+                (DriftResampler::delay(conf.blocklen) as f64 * 1e9 / conf.fs).round() as i64
+            },
+            rng,
+            gain: 10f64.powf(imp.gain / 20.0),
+            noise_amplitude: 10f64.powf(imp.noise / 20.0),
+            phase: 0.0,
+            phase_step: std::f64::consts::TAU * imp.freq_offset / conf.fs,
+            iq_gain: 10f64.powf(imp.iq_amplitude / 20.0),
+            iq_sin: iq_phase.sin(),
+            iq_cos: iq_phase.cos(),
+            imp,
+        }
+    }
+
+    fn process(&mut self, buf: &mut [StreamType]) {
+        for v in buf.iter_mut() {
+            let mut x = Complex::new(v.re as f64, v.im as f64);
+            if let Some(multipath) = &mut self.multipath {
+                x = multipath.process(x);
+            }
+            x *= self.gain * Complex::from_polar(1.0, self.phase);
+            self.phase = (self.phase + self.phase_step) % std::f64::consts::TAU;
+            if let Some(drift) = &mut self.drift {
+                drift.push(x);
+                x = drift.pop();
+            }
+            if self.noise_amplitude > 0.0 {
+                x += self.rng.complex_gaussian() * self.noise_amplitude;
+            }
+            if self.imp.iq_amplitude != 0.0 || self.imp.iq_phase != 0.0 {
+                x = Complex::new(x.re, self.iq_gain * (x.im * self.iq_cos - x.re * self.iq_sin));
+            }
+            *v = Complex::new(x.re as f32, x.im as f32);
+        }
+    }
+}
+
+pub struct ChannelIo {
+    loopback: LoopbackIo,
+    channel: Channel,
+}
+
+impl ChannelIo {
+    pub fn new(conf: &ChannelIoConfig) -> Result<Self, L1Error> {
+        Ok(Self {
+            loopback: LoopbackIo::new(&LoopbackIoConfig {
+                blocklen: conf.blocklen,
+                latency_blocks: conf.latency_blocks,
+                fs: conf.fs,
+                stop_time: conf.stop_time,
+            })?,
+            channel: Channel::new(conf),
+        })
+    }
+
+    /// Returns L1Error::EndOfStream after stop time has been reached.
+    pub fn process<F>(&mut self, mut process_signal: F) -> Result<(), L1Error>
+        where F: FnMut(&[Complex<f32>], i64, &mut [Complex<f32>], i64)
+    {
+        let channel = &mut self.channel;
+        self.loopback.process(|rx_buf, rx_time, tx_buf, tx_time| {
+            // Compensate for delay of the simulation, so that
+            // received signal stays aligned with transmit timestamps.
+            process_signal(rx_buf, rx_time - channel.delay_time, tx_buf, tx_time);
+            // Channel is applied to transmit signal before it goes
+            // to the loopback delay line, so that received signal
+            // does not need to be modified in place.
+            channel.process(tx_buf);
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_channel(impairments: Impairments) -> Channel {
+        Channel::new(&ChannelIoConfig {
+            blocklen: 1000,
+            latency_blocks: 1,
+            fs: 1e6,
+            freq: 400e6,
+            stop_time: 0,
+            impairments,
+        })
+    }
+
+    fn power(buf: &[StreamType]) -> f64 {
+        buf.iter().map(|v| v.norm_sqr() as f64).sum::<f64>() / buf.len() as f64
+    }
+
+    #[test]
+    fn test_noise_and_gain() {
+        let mut channel = test_channel(Impairments { noise: -20.0, ..Impairments::default() });
+        let mut buf = vec![num::zero(); 100000];
+        channel.process(&mut buf);
+        assert!((power(&buf) - 0.01).abs() < 0.0005, "{}", power(&buf));
+
+        let mut channel = test_channel(Impairments { gain: -6.0, ..Impairments::default() });
+        let mut buf = vec![Complex::new(1.0, 0.0); 1000];
+        channel.process(&mut buf);
+        assert!((power(&buf) - 0.2512).abs() < 0.001);
+    }
+
+    #[test]
+    fn test_freq_offset() {
+        let mut channel = test_channel(Impairments { freq_offset: 1000.0, ..Impairments::default() });
+        let mut buf = vec![Complex::new(1.0, 0.0); 1000];
+        channel.process(&mut buf);
+        // 1 kHz offset rotates phase by a full turn in 1 ms
+        assert!((buf[250] - Complex::new(0.0, 1.0)).norm() < 1e-3);
+        assert!((buf[500] - Complex::new(-1.0, 0.0)).norm() < 1e-3);
+    }
+
+    #[test]
+    fn test_drift() {
+        let mut channel = test_channel(Impairments { drift: 1000.0, ..Impairments::default() });
+        // Slow ramp, so that interpolation is exact enough
+        let mut buf: Vec<StreamType> = (0..20000).map(|i| Complex::new(i as f32 * 1e-5, 0.0)).collect();
+        channel.process(&mut buf);
+        // After initial margin of 1004 samples, receiver sampling
+        // 0.1 % faster should have advanced 0.1 % more in input.
+        let n = 15000;
+        let expected = (n as f64 * 1.001 - 1003.0) * 1e-5;
+        assert!((buf[n].re as f64 - expected).abs() < 1e-5, "{} {}", buf[n].re, expected);
+    }
+
+    #[test]
+    fn test_drift_long_run() {
+        let margin = 10;
+        for drift in [1000.0, -1000.0] {
+            let mut resampler = DriftResampler::new(drift, margin);
+            // Run ten times longer than it takes to use up the margin.
+            let n = 10 * (margin as f64 / (drift * 1e-6).abs()) as usize;
+            let freq = std::f64::consts::TAU * 1e-3;
+            for i in 0..n {
+                let x = Complex::from_polar(1.0, freq * i as f64);
+                resampler.push(x);
+                let y = resampler.pop();
+                assert!(resampler.input.len() <= 2 * margin + 4);
+                // Skip transient from the initial zero samples
+                if i < DriftResampler::delay(margin) + 4 {
+                    continue;
+                }
+                // Output shall not run out of input and shall stay
+                // within margin (plus one slipped sample) of its
+                // nominal delay.
+                let expected = Complex::from_polar(1.0,
+                    freq * (i - DriftResampler::delay(margin)) as f64);
+                assert!((y.norm() - 1.0).abs() < 1e-3, "{} {} {}", drift, i, y);
+                let offset = (y * expected.conj()).arg() / freq;
+                assert!(offset.abs() <= margin as f64 + 1.0, "{} {} {}", drift, i, offset);
+            }
+        }
+    }
+
+    #[test]
+    fn test_iq_imbalance() {
+        let mut channel = test_channel(Impairments { iq_amplitude: 6.0206, iq_phase: 30.0, ..Impairments::default() });
+        let mut buf = vec![Complex::new(1.0, 0.0), Complex::new(0.0, 1.0)];
+        channel.process(&mut buf);
+        assert!((buf[0] - Complex::new(1.0, -1.0)).norm() < 1e-3);
+        assert!((buf[1] - Complex::new(0.0, 1.7320)).norm() < 1e-3);
+    }
+
+    #[test]
+    fn test_fading() {
+        for fading in [FadingProfile::TypicalUrban, FadingProfile::HillyTerrain] {
+            let mut channel = test_channel(Impairments { fading, speed: 200.0, ..Impairments::default() });
+            let mut buf = vec![Complex::new(1.0, 0.0); 2000000];
+            channel.process(&mut buf);
+            // Average power over many fades should be close to 1,
+            // while power shall vary over time.
+            let p = power(&buf);
+            assert!(p > 0.5 && p < 1.5, "{:?} {}", fading, p);
+            let p_min = buf.chunks(1000).map(power).fold(f64::INFINITY, f64::min);
+            assert!(p_min < 0.2, "{:?} {}", fading, p_min);
+        }
+    }
+}
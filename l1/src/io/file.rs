@@ -1,12 +1,72 @@
 //! File I/O for signals.
-//! This is useful for testing the signal processing chain.
+//! This is useful for testing the signal processing chain
+//! and for replaying recorded signals.
 
 use std::fs::File;
-use std::io::Write;
+use std::io::{BufReader, BufWriter, ErrorKind, Read, Write};
 use num::Complex;
+use crate::{L1Error, L1Stats};
+use crate::timebase::Timebase;
 
 type StreamType = Complex<f32>;
 
+/// Sample format of an I/Q file.
+/// Samples are interleaved I and Q values.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum SampleFormat {
+    /// 32-bit float, little endian.
+    /// Used by GNU Radio and many other tools.
+    Cf32,
+    /// 16-bit signed integer, little endian.
+    /// Full scale of 32768 corresponds to 1.0.
+    Cs16,
+    /// 8-bit unsigned integer with an offset of 127.5,
+    /// as produced by rtl_sdr.
+    Cu8,
+}
+
+impl SampleFormat {
+    /// Number of bytes in one complex sample.
+    pub fn sample_bytes(self) -> usize {
+        match self {
+            SampleFormat::Cf32 => 8,
+            SampleFormat::Cs16 => 4,
+            SampleFormat::Cu8  => 2,
+        }
+    }
+
+    /// Convert samples from bytes in this format.
+    /// Length of bytes shall be sample_bytes() times length of samples.
+    pub fn decode(self, bytes: &[u8], samples: &mut [StreamType]) {
+        assert_eq!(bytes.len(), samples.len() * self.sample_bytes());
+        let decode_one: fn(&[u8]) -> f32 = match self {
+            SampleFormat::Cf32 => |b| f32::from_le_bytes([b[0], b[1], b[2], b[3]]),
+            SampleFormat::Cs16 => |b| i16::from_le_bytes([b[0], b[1]]) as f32 * (1.0 / 32768.0),
+            SampleFormat::Cu8  => |b| (b[0] as f32 - 127.5) * (1.0 / 127.5),
+        };
+        let half = self.sample_bytes() / 2;
+        for (sample, b) in samples.iter_mut().zip(bytes.chunks_exact(self.sample_bytes())) {
+            *sample = Complex::new(decode_one(&b[..half]), decode_one(&b[half..]));
+        }
+    }
+
+    /// Convert samples to bytes in this format, appending them to bytes.
+    /// Values outside the range of integer formats are clipped.
+    pub fn encode(self, samples: &[StreamType], bytes: &mut Vec<u8>) {
+        for sample in samples {
+            for v in [sample.re, sample.im] {
+                match self {
+                    SampleFormat::Cf32 => bytes.extend_from_slice(&v.to_le_bytes()),
+                    SampleFormat::Cs16 => bytes.extend_from_slice(
+                        &((v * 32768.0).round().clamp(-32768.0, 32767.0) as i16).to_le_bytes()),
+                    SampleFormat::Cu8  => bytes.push(
+                        (v * 127.5 + 127.5).round().clamp(0.0, 255.0) as u8),
+                }
+            }
+        }
+    }
+}
+
 pub struct FileIoConfig<'a> {
     /// Processing block length in samples
     pub blocklen: usize,
@@ -14,58 +74,186 @@ pub struct FileIoConfig<'a> {
     pub fs: f64,
     /// Stop after this number of nanoseconds
     pub stop_time: i64,
+    /// Input file name for receive signal.
+    /// If None, zeros are used as receive signal.
+    /// Processing stops at the end of the file.
+    pub rx_filename: Option<&'a str>,
+    /// Format of receive signal file.
+    pub rx_format: SampleFormat,
     /// Output file name for transmit signal.
-    pub tx_filename: &'a str,
+    /// If None, transmit signal is not written.
+    pub tx_filename: Option<&'a str>,
+    /// Format of transmit signal file.
+    pub tx_format: SampleFormat,
 }
 
 pub struct FileIo {
-    tx_file: File,
-    time: i64,
-    time_per_buf: i64,
+    rx_file: Option<BufReader<File>>,
+    rx_format: SampleFormat,
+    tx_file: Option<BufWriter<File>>,
+    tx_format: SampleFormat,
+    timebase: Timebase,
+    /// Number of samples processed
+    sample: i64,
     stop_time: i64,
-    buf: Vec<StreamType>,
+    rx_buf: Vec<StreamType>,
+    tx_buf: Vec<StreamType>,
+    // Buffer for file contents in external format
+    filebuf: Vec<u8>,
 }
 
-impl FileIo {
-    pub fn new(conf: &FileIoConfig) -> Option<Self> {
-        Some(Self {
-            tx_file: match File::create(conf.tx_filename) {
-                Ok(file) => file,
-                Err(err) => {
-                    eprintln!("Failed to open TX file: {}", err);
-                    return None;
+/// Read a whole block of samples.
+/// Returns false at the end of file. A partial block at the end
+/// is counted as a short read and its samples as dropped.
+pub fn read_block(reader: &mut impl Read, buf: &mut [u8], sample_bytes: usize, stats: &mut L1Stats) -> std::io::Result<bool> {
+    let mut filled = 0;
+    while filled < buf.len() {
+        match reader.read(&mut buf[filled..]) {
+            Ok(0) => {
+                if filled > 0 {
+                    stats.short_reads += 1;
+                    stats.dropped_samples += (filled / sample_bytes) as u64;
                 }
+                return Ok(false);
             },
-            time: 0,
-            time_per_buf: (conf.blocklen as f64 * 1e9 / conf.fs).round() as i64,
+            Ok(n) => filled += n,
+            Err(err) if err.kind() == ErrorKind::Interrupted => {},
+            Err(err) => return Err(err),
+        }
+    }
+    Ok(true)
+}
+
+impl FileIo {
+    pub fn new(conf: &FileIoConfig) -> Result<Self, L1Error> {
+        Ok(Self {
+            rx_file: match conf.rx_filename {
+                Some(filename) => Some(BufReader::new(File::open(filename).map_err(|err|
+                    L1Error::Io(format!("Failed to open RX file {}: {}", filename, err)))?)),
+                None => None,
+            },
+            rx_format: conf.rx_format,
+            tx_file: match conf.tx_filename {
+                Some(filename) => Some(BufWriter::new(File::create(filename).map_err(|err|
+                    L1Error::Io(format!("Failed to open TX file {}: {}", filename, err)))?)),
+                None => None,
+            },
+            tx_format: conf.tx_format,
+            timebase: Timebase::new(conf.fs),
+            sample: 0,
             stop_time: conf.stop_time,
-            buf: vec![num::zero(); conf.blocklen],
+            rx_buf: vec![num::zero(); conf.blocklen],
+            tx_buf: vec![num::zero(); conf.blocklen],
+            filebuf: Vec::with_capacity(conf.blocklen * 8),
         })
     }
 
-    pub fn process<F>(&mut self, mut process_signal: F) -> Option<()>
-        where F: FnMut(&mut [Complex<f32>], i64, i64)
+    /// Returns L1Error::EndOfStream at the end of RX file
+    /// or after stop time has been reached.
+    pub fn process<F>(&mut self, stats: &mut L1Stats, mut process_signal: F) -> Result<(), L1Error>
+        where F: FnMut(&[Complex<f32>], i64, &mut [Complex<f32>], i64)
     {
-        let buf_slice = &mut self.buf[..];
-        // Use zero as RX signal for now
-        for v in &mut *buf_slice { *v = num::zero(); }
-
-        process_signal(&mut *buf_slice, self.time, self.time);
-
-        // Let's be a bit lazy and use transmute to write the buffer to file.
-        // Yes, the file format ends up depending on machine endianness etc,
-        // so it's unsafe.
-        // This is for initial testing purposes only.
-        match self.tx_file.write_all(
-        unsafe { std::mem::transmute::<&[StreamType], &[u8]>(buf_slice) }) {
-            Ok(_) => {},
-            Err(err) => {
-                eprintln!("Failed to write TX file: {}", err);
-                return None;
+        if let Some(rx_file) = &mut self.rx_file {
+            self.filebuf.resize(self.rx_buf.len() * self.rx_format.sample_bytes(), 0);
+            match read_block(rx_file, &mut self.filebuf[..], self.rx_format.sample_bytes(), stats) {
+                Ok(true) => {},
+                Ok(false) =>
+                    return Err(L1Error::EndOfStream),
+                Err(err) =>
+                    return Err(L1Error::Io(format!("Failed to read RX file: {}", err))),
             }
-        };
+            self.rx_format.decode(&self.filebuf[..], &mut self.rx_buf[..]);
+        }
+
+        let time = self.timebase.samples_to_ns(self.sample);
+        process_signal(&self.rx_buf[..], time, &mut self.tx_buf[..], time);
+
+        if let Some(tx_file) = &mut self.tx_file {
+            self.filebuf.clear();
+            self.tx_format.encode(&self.tx_buf[..], &mut self.filebuf);
+            tx_file.write_all(&self.filebuf[..]).map_err(|err|
+                L1Error::Io(format!("Failed to write TX file: {}", err)))?;
+        }
+
+        self.sample += self.rx_buf.len() as i64;
+        if self.timebase.samples_to_ns(self.sample) >= self.stop_time { Err(L1Error::EndOfStream) } else { Ok(()) }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sample_formats() {
+        let samples = [
+            Complex::new(0.0, -1.0),
+            Complex::new(0.5, -0.25),
+            Complex::new(2.0, -2.0),
+        ];
+        for (format, tolerance) in [
+            (SampleFormat::Cf32, 0.0),
+            (SampleFormat::Cs16, 1.0 / 32768.0),
+            (SampleFormat::Cu8,  1.0 / 127.5),
+        ] {
+            let mut bytes = Vec::new();
+            format.encode(&samples, &mut bytes);
+            assert_eq!(bytes.len(), samples.len() * format.sample_bytes());
+            let mut decoded = [num::zero(); 3];
+            format.decode(&bytes, &mut decoded);
+            for (s, d) in samples.iter().zip(decoded.iter()) {
+                // Integer formats are clipped to just below 1.0
+                let expected = if format == SampleFormat::Cf32 { *s } else {
+                    Complex::new(s.re.clamp(-1.0, 1.0), s.im.clamp(-1.0, 1.0))
+                };
+                assert!((expected - d).norm() <= tolerance * 1.5, "{:?}: {} != {}", format, expected, d);
+            }
+        }
+        // Check byte order of integer format
+        let mut bytes = Vec::new();
+        SampleFormat::Cs16.encode(&[Complex::new(0.5, -0.5)], &mut bytes);
+        assert_eq!(bytes, vec![0x00, 0x40, 0x00, 0xC0]);
+    }
+
+    #[test]
+    fn test_file_replay() {
+        let dir = std::env::temp_dir();
+        let rx_path = dir.join(format!("l1_test_rx_{}.cs16", std::process::id()));
+        let tx_path = dir.join(format!("l1_test_tx_{}.cf32", std::process::id()));
+        let rx_samples: Vec<StreamType> = (0..25).map(|i| Complex::new(i as f32 / 32.0, 0.5)).collect();
+        let mut bytes = Vec::new();
+        SampleFormat::Cs16.encode(&rx_samples, &mut bytes);
+        std::fs::write(&rx_path, &bytes).unwrap();
+
+        let mut io = FileIo::new(&FileIoConfig {
+            blocklen: 10,
+            fs: 1e6,
+            stop_time: 1_000_000,
+            rx_filename: Some(rx_path.to_str().unwrap()),
+            rx_format: SampleFormat::Cs16,
+            tx_filename: Some(tx_path.to_str().unwrap()),
+            tx_format: SampleFormat::Cf32,
+        }).unwrap();
+
+        let mut received = Vec::new();
+        let mut stats = L1Stats::default();
+        // Only full blocks are processed, so the last 5 samples are skipped.
+        while io.process(&mut stats, |rx_buf, _, tx_buf, _| {
+            received.extend_from_slice(rx_buf);
+            for (tx, rx) in tx_buf.iter_mut().zip(rx_buf.iter()) { *tx = *rx * 2.0; }
+        }).is_ok() {}
+        assert_eq!(&received[..], &rx_samples[..20]);
+        assert_eq!(stats, L1Stats { short_reads: 1, dropped_samples: 5, ..L1Stats::default() });
+        drop(io);
+
+        let tx_bytes = std::fs::read(&tx_path).unwrap();
+        let mut transmitted = vec![num::zero(); 20];
+        SampleFormat::Cf32.decode(&tx_bytes, &mut transmitted);
+        for (rx, tx) in rx_samples.iter().zip(transmitted.iter()) {
+            assert_eq!(rx * 2.0, *tx);
+        }
 
-        self.time += self.time_per_buf;
-        if self.time >= self.stop_time { None } else { Some(()) }
+        std::fs::remove_file(&rx_path).unwrap();
+        std::fs::remove_file(&tx_path).unwrap();
     }
 }
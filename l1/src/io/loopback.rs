@@ -0,0 +1,95 @@
+//! Software loopback connecting transmit signal to receive signal.
+//! This allows testing the whole signal processing chain
+//! without any radio hardware.
+
+use std::collections::VecDeque;
+use num::Complex;
+use crate::L1Error;
+use crate::timebase::Timebase;
+
+type StreamType = Complex<f32>;
+
+pub struct LoopbackIoConfig {
+    /// Processing block length in samples
+    pub blocklen: usize,
+    /// RX-TX round-trip latency as a multiple of processing block length.
+    /// Transmit signal is received this many blocks later.
+    pub latency_blocks: usize,
+    /// Sample rate
+    pub fs: f64,
+    /// Stop after this number of nanoseconds
+    pub stop_time: i64,
+}
+
+pub struct LoopbackIo {
+    /// Transmitted blocks waiting to be received
+    delay_line: VecDeque<Vec<StreamType>>,
+    timebase: Timebase,
+    /// Number of samples processed
+    sample: i64,
+    latency_time: i64,
+    stop_time: i64,
+    /// Buffer for transmit signal
+    tx_buf: Vec<StreamType>,
+}
+
+impl LoopbackIo {
+    pub fn new(conf: &LoopbackIoConfig) -> Result<Self, L1Error> {
+        if conf.latency_blocks == 0 {
+            return Err(L1Error::Config("loopback latency shall be at least 1 block".to_string()));
+        }
+        Ok(Self {
+            delay_line: (0..conf.latency_blocks).map(|_| vec![num::zero(); conf.blocklen]).collect(),
+            timebase: Timebase::new(conf.fs),
+            sample: 0,
+            latency_time: Timebase::new(conf.fs).samples_to_ns((conf.blocklen * conf.latency_blocks) as i64),
+            stop_time: conf.stop_time,
+            tx_buf: vec![num::zero(); conf.blocklen],
+        })
+    }
+
+    /// Returns L1Error::EndOfStream after stop time has been reached.
+    pub fn process<F>(&mut self, mut process_signal: F) -> Result<(), L1Error>
+        where F: FnMut(&[Complex<f32>], i64, &mut [Complex<f32>], i64)
+    {
+        // Oldest transmitted block becomes the received block.
+        let rx_buf = self.delay_line.pop_front().unwrap();
+
+        let time = self.timebase.samples_to_ns(self.sample);
+        process_signal(&rx_buf[..], time, &mut self.tx_buf[..], time + self.latency_time);
+
+        // Reuse the received buffer for the next transmit block
+        // to avoid allocating a new one.
+        let tx_buf = std::mem::replace(&mut self.tx_buf, rx_buf);
+        self.delay_line.push_back(tx_buf);
+
+        self.sample += self.tx_buf.len() as i64;
+        if self.timebase.samples_to_ns(self.sample) >= self.stop_time { Err(L1Error::EndOfStream) } else { Ok(()) }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_loopback_latency() {
+        let mut io = LoopbackIo::new(&LoopbackIoConfig {
+            blocklen: 4,
+            latency_blocks: 2,
+            fs: 1e6,
+            stop_time: 10_000,
+        }).unwrap();
+        let mut block = 0;
+        while io.process(|rx_buf, rx_time, tx_buf, tx_time| {
+            assert_eq!(rx_time, block as i64 * 4000);
+            assert_eq!(tx_time, rx_time + 8000);
+            // Received block was transmitted 2 blocks earlier
+            let expected = if block >= 2 { (block - 2) as f32 } else { 0.0 };
+            assert!(rx_buf.iter().all(|v| v.re == expected));
+            for v in tx_buf.iter_mut() { *v = Complex::new(block as f32, 0.0); }
+            block += 1;
+        }).is_ok() {}
+        assert_eq!(block, 3);
+    }
+}
@@ -1,35 +1,95 @@
 use num::Complex;
+use crate::{L1Error, L1Stats};
+pub mod channel;
 pub mod file;
+pub mod loopback;
+pub mod net;
+pub mod sigmf;
 pub mod soapy;
 
 pub enum RadioIoConfig<'a> {
     File(&'a file::FileIoConfig<'a>),
+    Sigmf(&'a sigmf::SigmfIoConfig<'a>),
+    Loopback(&'a loopback::LoopbackIoConfig),
+    Channel(&'a channel::ChannelIoConfig),
+    Net(&'a net::NetIoConfig<'a>),
     Soapy(&'a soapy::SoapyIoConfig<'a>),
 }
 
 enum RadioIoEnum {
     File(file::FileIo),
+    Sigmf(sigmf::SigmfIo),
+    Loopback(loopback::LoopbackIo),
+    Channel(channel::ChannelIo),
+    Net(net::NetIo),
     Soapy(soapy::SoapyIo),
 }
 
-pub struct RadioIo(RadioIoEnum);
+pub struct RadioIo(RadioIoEnum, L1Stats);
 
 impl RadioIo {
-    pub fn new(conf: &RadioIoConfig) -> Option<Self> {
-        Some(RadioIo(match conf {
+    pub fn new(conf: &RadioIoConfig) -> Result<Self, L1Error> {
+        Ok(RadioIo(match conf {
             RadioIoConfig::File(conf) =>
                 RadioIoEnum::File(file::FileIo::new(conf)?),
+            RadioIoConfig::Sigmf(conf) =>
+                RadioIoEnum::Sigmf(sigmf::SigmfIo::new(conf)?),
+            RadioIoConfig::Loopback(conf) =>
+                RadioIoEnum::Loopback(loopback::LoopbackIo::new(conf)?),
+            RadioIoConfig::Channel(conf) =>
+                RadioIoEnum::Channel(channel::ChannelIo::new(conf)?),
+            RadioIoConfig::Net(conf) =>
+                RadioIoEnum::Net(net::NetIo::new(conf)?),
             RadioIoConfig::Soapy(conf) =>
                 RadioIoEnum::Soapy(soapy::SoapyIo::new(conf)?),
-        }))
+        }, L1Stats::default()))
     }
 
-    pub fn process<F>(&mut self, mut process_signal: F) -> Option<()>
-        where F: FnMut(&mut [Complex<f32>], i64, i64)
+    pub fn stats(&self) -> L1Stats {
+        self.1
+    }
+
+    /// Receive and transmit a block of signal.
+    /// process_signal is called with a block of received signal and
+    /// its timestamp, and a block of transmit signal to be produced
+    /// and its timestamp. It shall write the whole transmit block.
+    /// Lengths of the blocks may differ.
+    pub fn process<F>(&mut self, mut process_signal: F) -> Result<(), L1Error>
+        where F: FnMut(&[Complex<f32>], i64, &mut [Complex<f32>], i64)
     {
-        match self.0 {
-            RadioIoEnum::File(ref mut io) => io.process(&mut process_signal),
-            RadioIoEnum::Soapy(ref mut io) => io.process(&mut process_signal),
-        }
+        let stats = &mut self.1;
+        let mut blocks = 0;
+        let mut process_signal = |rx_buf: &[Complex<f32>], rx_time, tx_buf: &mut [Complex<f32>], tx_time| {
+            blocks += 1;
+            process_signal(rx_buf, rx_time, tx_buf, tx_time)
+        };
+        let result = match self.0 {
+            RadioIoEnum::File(ref mut io) => io.process(stats, &mut process_signal),
+            RadioIoEnum::Sigmf(ref mut io) => io.process(stats, &mut process_signal),
+            RadioIoEnum::Loopback(ref mut io) => io.process(&mut process_signal),
+            RadioIoEnum::Channel(ref mut io) => io.process(&mut process_signal),
+            RadioIoEnum::Net(ref mut io) => io.process(stats, &mut process_signal),
+            RadioIoEnum::Soapy(ref mut io) => io.process(stats, &mut process_signal),
+        };
+        stats.blocks += blocks;
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_block_stats() {
+        let mut radio = RadioIo::new(&RadioIoConfig::Loopback(&loopback::LoopbackIoConfig {
+            blocklen: 4,
+            latency_blocks: 1,
+            fs: 1e6,
+            stop_time: 10_000,
+        })).unwrap();
+        while radio.process(|_, _, _, _| {}).is_ok() {}
+        // Block ending with EndOfStream is also processed
+        assert_eq!(radio.stats(), L1Stats { blocks: 3, ..L1Stats::default() });
     }
 }
@@ -0,0 +1,463 @@
+//! Network I/O for signals.
+//!
+//! Exchanges timestamped I/Q sample blocks with a remote end
+//! over TCP or UDP, so that signal processing can run on
+//! a different host from the radio.
+//!
+//! # Framing
+//!
+//! Both directions use the same frame format.
+//! All values are little endian.
+//!
+//! | Offset | Size | Content                                      |
+//! |--------|------|----------------------------------------------|
+//! | 0      | 4    | Magic bytes `TQIQ`                           |
+//! | 4      | 8    | Timestamp of the first sample (i64, ns)      |
+//! | 12     | 4    | Number of samples N (u32)                    |
+//! | 16     | 8*N  | Samples as interleaved I and Q (f32)         |
+//!
+//! Frames sent by the remote end contain received signal,
+//! timestamped with the time the first sample was received.
+//! They may be of any length, and L1 combines them to processing blocks.
+//! Frames sent by L1 contain transmit signal, timestamped with the time
+//! the first sample should be transmitted. The timestamp is ahead of
+//! received signal by the configured latency.
+//! Timestamps are in nanoseconds in the time base of the remote end.
+//!
+//! Over TCP, frames are sent back-to-back on a connection to the remote end.
+//! Over UDP, each datagram carries one frame, so a frame has at most
+//! MAX_FRAME_SAMPLES samples. L1 begins by sending a frame with no samples,
+//! so that the remote end learns where to send received signal.
+//!
+//! Frames received by L1 are rejected if they have more than
+//! MAX_RX_FRAME_BLOCKS processing blocks or MAX_FRAME_SAMPLES samples,
+//! whichever is more. If no signal is received within RECEIVE_TIMEOUT,
+//! processing returns a timeout error and can be retried.
+
+use std::collections::VecDeque;
+use std::io::{Read, Write};
+use std::net::{TcpStream, UdpSocket};
+use std::time::Duration;
+use num::Complex;
+use crate::{L1Error, L1Stats};
+use crate::timebase::Timebase;
+
+type StreamType = Complex<f32>;
+
+/// Magic bytes at the beginning of each frame
+pub const FRAME_MAGIC: [u8; 4] = *b"TQIQ";
+/// Length of frame header in bytes
+pub const HEADER_LEN: usize = 16;
+/// Maximum number of samples in a frame sent by L1.
+/// This keeps frames within the maximum size of an UDP datagram.
+pub const MAX_FRAME_SAMPLES: usize = 8000;
+/// Maximum length of a received frame in processing blocks,
+/// so that a corrupted header cannot make L1 allocate a huge buffer.
+pub const MAX_RX_FRAME_BLOCKS: usize = 4;
+/// Time to wait for received signal before returning a timeout error
+pub const RECEIVE_TIMEOUT: Duration = Duration::from_millis(100);
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum Transport {
+    Tcp,
+    Udp,
+}
+
+pub struct NetIoConfig<'a> {
+    /// Processing block length in samples
+    pub blocklen: usize,
+    /// RX-TX round-trip latency as a multiple of processing block length.
+    pub latency_blocks: usize,
+    /// Sample rate
+    pub fs: f64,
+    /// Transport protocol
+    pub transport: Transport,
+    /// Address of the remote end, such as 192.168.1.2:4000
+    pub address: &'a str,
+    /// Local address to bind to when using UDP
+    pub local_address: &'a str,
+}
+
+/// Append a frame to a buffer.
+pub fn encode_frame(time: i64, samples: &[StreamType], bytes: &mut Vec<u8>) {
+    bytes.extend_from_slice(&FRAME_MAGIC);
+    bytes.extend_from_slice(&time.to_le_bytes());
+    bytes.extend_from_slice(&(samples.len() as u32).to_le_bytes());
+    for sample in samples {
+        bytes.extend_from_slice(&sample.re.to_le_bytes());
+        bytes.extend_from_slice(&sample.im.to_le_bytes());
+    }
+}
+
+/// Parse a frame header, returning timestamp and number of samples.
+pub fn decode_header(header: &[u8]) -> Result<(i64, usize), String> {
+    if header.len() < HEADER_LEN || header[0..4] != FRAME_MAGIC {
+        return Err("invalid frame header".to_string());
+    }
+    Ok((
+        i64::from_le_bytes(header[4..12].try_into().unwrap()),
+        u32::from_le_bytes(header[12..16].try_into().unwrap()) as usize,
+    ))
+}
+
+/// Parse a header of a received frame,
+/// checking that the frame is not too long.
+fn decode_rx_header(header: &[u8], max_samples: usize) -> Result<(i64, usize), L1Error> {
+    let (time, n) = decode_header(header).map_err(receive_error)?;
+    if n > max_samples {
+        return Err(receive_error(format!(
+            "frame of {} samples is longer than maximum of {}", n, max_samples)));
+    }
+    Ok((time, n))
+}
+
+fn receive_error(msg: impl std::fmt::Display) -> L1Error {
+    L1Error::Io(format!("Failed to receive signal: {}", msg))
+}
+
+/// Convert socket error to L1Error.
+/// Read timeout is reported as WouldBlock or TimedOut depending on platform.
+fn convert_io_error(err: std::io::Error) -> L1Error {
+    match err.kind() {
+        std::io::ErrorKind::WouldBlock | std::io::ErrorKind::TimedOut => L1Error::Timeout,
+        _ => receive_error(err),
+    }
+}
+
+/// Parse samples of a frame, appending them to a buffer.
+pub fn decode_samples(bytes: &[u8], samples: &mut VecDeque<StreamType>) {
+    for b in bytes.chunks_exact(8) {
+        samples.push_back(Complex::new(
+            f32::from_le_bytes(b[0..4].try_into().unwrap()),
+            f32::from_le_bytes(b[4..8].try_into().unwrap()),
+        ));
+    }
+}
+
+enum Socket {
+    Tcp(TcpStream),
+    Udp(UdpSocket),
+}
+
+pub struct NetIo {
+    socket: Socket,
+    timebase: Timebase,
+    latency_time: i64,
+    /// Received samples not yet processed
+    pending: VecDeque<StreamType>,
+    /// Timestamp received stream was last synchronized to
+    anchor_time: i64,
+    /// Index of the first sample in pending, counted from anchor_time
+    pending_sample: i64,
+    rx_buf: Vec<StreamType>,
+    tx_buf: Vec<StreamType>,
+    /// Maximum number of samples in a received frame
+    max_rx_frame_samples: usize,
+    /// Buffer for frames in external format
+    framebuf: Vec<u8>,
+    /// Number of bytes of a TCP frame received so far.
+    /// A frame may arrive over several calls if reading times out.
+    tcp_received: usize,
+}
+
+impl NetIo {
+    pub fn new(conf: &NetIoConfig) -> Result<Self, L1Error> {
+        Self::connect(conf).map_err(|err|
+            L1Error::DeviceOpen(format!("Failed to connect to {}: {}", conf.address, err)))
+    }
+
+    fn connect(conf: &NetIoConfig) -> std::io::Result<Self> {
+        let socket = match conf.transport {
+            Transport::Tcp => {
+                let stream = TcpStream::connect(conf.address)?;
+                stream.set_nodelay(true)?;
+                stream.set_read_timeout(Some(RECEIVE_TIMEOUT))?;
+                Socket::Tcp(stream)
+            },
+            Transport::Udp => {
+                let socket = UdpSocket::bind(conf.local_address)?;
+                socket.connect(conf.address)?;
+                socket.set_read_timeout(Some(RECEIVE_TIMEOUT))?;
+                let mut hello = Vec::new();
+                encode_frame(0, &[], &mut hello);
+                socket.send(&hello)?;
+                Socket::Udp(socket)
+            },
+        };
+        let max_rx_frame_samples = (conf.blocklen * MAX_RX_FRAME_BLOCKS).max(MAX_FRAME_SAMPLES);
+        Ok(Self {
+            socket,
+            timebase: Timebase::new(conf.fs),
+            latency_time: Timebase::new(conf.fs).samples_to_ns((conf.blocklen * conf.latency_blocks) as i64),
+            pending: VecDeque::with_capacity(conf.blocklen * 2),
+            anchor_time: 0,
+            pending_sample: 0,
+            rx_buf: vec![num::zero(); conf.blocklen],
+            tx_buf: vec![num::zero(); conf.blocklen],
+            max_rx_frame_samples,
+            framebuf: Vec::with_capacity(HEADER_LEN + max_rx_frame_samples * 8),
+            tcp_received: 0,
+        })
+    }
+
+    /// Receive a frame into framebuf.
+    /// Returns its timestamp and length in bytes.
+    fn receive_bytes(&mut self) -> Result<(i64, usize), L1Error> {
+        let max_samples = self.max_rx_frame_samples;
+        match &mut self.socket {
+            Socket::Tcp(stream) => {
+                // Header is read first, and then the rest of the frame
+                // whose length is given in the header.
+                loop {
+                    let mut frame_len = HEADER_LEN;
+                    if self.tcp_received >= HEADER_LEN {
+                        let (time, n) = decode_rx_header(&self.framebuf[..HEADER_LEN], max_samples)?;
+                        frame_len = HEADER_LEN + n * 8;
+                        if self.tcp_received == frame_len {
+                            self.tcp_received = 0;
+                            return Ok((time, frame_len));
+                        }
+                    }
+                    self.framebuf.resize(frame_len, 0);
+                    match stream.read(&mut self.framebuf[self.tcp_received..]) {
+                        Ok(0) => return Err(receive_error("connection closed")),
+                        Ok(len) => self.tcp_received += len,
+                        Err(err) => return Err(convert_io_error(err)),
+                    }
+                }
+            },
+            Socket::Udp(socket) => {
+                self.framebuf.resize(65536, 0);
+                let len = socket.recv(&mut self.framebuf[..]).map_err(convert_io_error)?;
+                let (time, n) = decode_rx_header(&self.framebuf[..len], max_samples)?;
+                if len != HEADER_LEN + n * 8 {
+                    return Err(receive_error("frame length does not match datagram length"));
+                }
+                Ok((time, len))
+            },
+        }
+    }
+
+    /// Receive a frame and add its samples to pending samples.
+    fn receive_frame(&mut self, stats: &mut L1Stats) -> Result<(), L1Error> {
+        let (time, len) = self.receive_bytes().inspect_err(|err| {
+            if *err == L1Error::Timeout {
+                stats.timeouts += 1;
+            }
+        })?;
+        if self.pending.is_empty() {
+            self.anchor_time = time;
+            self.pending_sample = 0;
+        } else {
+            let expected = self.anchor_time + self.timebase.samples_to_ns(self.pending_sample + self.pending.len() as i64);
+            if self.timebase.is_discontinuous(time, expected) {
+                log_limited!(crate::log::L1LogLevel::Warning,
+                    "Discontinuity in received signal: expected time {}, got {}", expected, time);
+                // Pending samples are discarded, and if the new frame is
+                // later than expected, samples in between are missing.
+                let missing = self.timebase.ns_to_samples(time - expected).max(0) as u64;
+                stats.dropped_samples += self.pending.len() as u64 + missing;
+                self.pending.clear();
+                self.anchor_time = time;
+                self.pending_sample = 0;
+            }
+        }
+        decode_samples(&self.framebuf[HEADER_LEN..len], &mut self.pending);
+        Ok(())
+    }
+
+    fn send_frames(&mut self, tx_time: i64) -> Result<(), String> {
+        for (i, chunk) in self.tx_buf.chunks(MAX_FRAME_SAMPLES).enumerate() {
+            self.framebuf.clear();
+            encode_frame(tx_time + self.timebase.samples_to_ns((i * MAX_FRAME_SAMPLES) as i64), chunk, &mut self.framebuf);
+            match &mut self.socket {
+                Socket::Tcp(stream) => stream.write_all(&self.framebuf),
+                Socket::Udp(socket) => socket.send(&self.framebuf).map(|_| ()),
+            }.map_err(|err| err.to_string())?;
+        }
+        Ok(())
+    }
+
+    pub fn process<F>(&mut self, stats: &mut L1Stats, mut process_signal: F) -> Result<(), L1Error>
+        where F: FnMut(&[Complex<f32>], i64, &mut [Complex<f32>], i64)
+    {
+        while self.pending.len() < self.rx_buf.len() {
+            self.receive_frame(stats)?;
+        }
+        let rx_time = self.anchor_time + self.timebase.samples_to_ns(self.pending_sample);
+        for v in self.rx_buf.iter_mut() {
+            *v = self.pending.pop_front().unwrap();
+        }
+        self.pending_sample += self.rx_buf.len() as i64;
+
+        let tx_time = rx_time + self.latency_time;
+        process_signal(&self.rx_buf[..], rx_time, &mut self.tx_buf[..], tx_time);
+
+        self.send_frames(tx_time).map_err(|err|
+            L1Error::Io(format!("Failed to send signal: {}", err)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::TcpListener;
+
+    /// Stand-in for the remote end: sends RX frames of different
+    /// lengths with a gap between them, and returns TX frames.
+    fn remote_tcp(listener: TcpListener) -> Vec<(i64, Vec<StreamType>)> {
+        let (mut stream, _) = listener.accept().unwrap();
+        let mut bytes = Vec::new();
+        // 1 MHz sample rate, so each sample is 1000 ns
+        let mut time = 5_000_000;
+        for n in [3, 7, 5, 5] {
+            let samples: Vec<StreamType> = (0..n).map(|i| Complex::new(time as f32, i as f32)).collect();
+            encode_frame(time, &samples, &mut bytes);
+            time += n as i64 * 1000;
+        }
+        // Gap of one sample
+        encode_frame(time + 1000, &[num::zero(); 10], &mut bytes);
+        stream.write_all(&bytes).unwrap();
+
+        let mut tx_frames = Vec::new();
+        let mut header = [0u8; HEADER_LEN];
+        while stream.read_exact(&mut header).is_ok() {
+            let (time, n) = decode_header(&header).unwrap();
+            let mut data = vec![0u8; n * 8];
+            stream.read_exact(&mut data).unwrap();
+            let mut samples = VecDeque::new();
+            decode_samples(&data, &mut samples);
+            tx_frames.push((time, samples.into()));
+        }
+        tx_frames
+    }
+
+    #[test]
+    fn test_net_tcp() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        let remote = std::thread::spawn(move || remote_tcp(listener));
+
+        let mut io = NetIo::new(&NetIoConfig {
+            blocklen: 10,
+            latency_blocks: 2,
+            fs: 1e6,
+            transport: Transport::Tcp,
+            address: &address,
+            local_address: "",
+        }).unwrap();
+        let mut stats = L1Stats::default();
+        let mut rx_times = Vec::new();
+        // First 20 samples form two blocks, then the block after the gap.
+        for _ in 0..3 {
+            io.process(&mut stats, |rx_buf, rx_time, tx_buf, tx_time| {
+                assert_eq!(tx_time, rx_time + 20_000);
+                if rx_times.len() < 2 {
+                    // Samples carry their frame timestamp, so check
+                    // they are in the right order.
+                    assert!(rx_buf.windows(2).all(|w| w[0].re <= w[1].re));
+                }
+                rx_times.push(rx_time);
+                for v in tx_buf.iter_mut() { *v = Complex::new(rx_time as f32, 1.0); }
+            }).unwrap();
+        }
+        assert_eq!(rx_times, vec![5_000_000, 5_010_000, 5_021_000]);
+        drop(io);
+
+        let tx_frames = remote.join().unwrap();
+        assert_eq!(tx_frames.len(), 3);
+        for (rx_time, (tx_time, samples)) in rx_times.iter().zip(tx_frames.iter()) {
+            assert_eq!(*tx_time, rx_time + 20_000);
+            assert_eq!(samples, &vec![Complex::new(*rx_time as f32, 1.0); 10]);
+        }
+    }
+
+    #[test]
+    fn test_net_tcp_timeout_and_invalid_frame() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        let remote = std::thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut frame = Vec::new();
+            encode_frame(0, &[Complex::new(1.0, 2.0); 10], &mut frame);
+            // Frame is split by a pause longer than receive timeout
+            for part in [&frame[..20], &frame[20..]] {
+                std::thread::sleep(RECEIVE_TIMEOUT * 3);
+                stream.write_all(part).unwrap();
+            }
+            // Header of a frame much longer than allowed
+            let mut header = Vec::new();
+            encode_frame(10_000, &[], &mut header);
+            header[12..16].copy_from_slice(&u32::MAX.to_le_bytes());
+            stream.write_all(&header).unwrap();
+            stream
+        });
+
+        let mut io = NetIo::new(&NetIoConfig {
+            blocklen: 10,
+            latency_blocks: 2,
+            fs: 1e6,
+            transport: Transport::Tcp,
+            address: &address,
+            local_address: "",
+        }).unwrap();
+        let mut stats = L1Stats::default();
+        let mut blocks = 0;
+        loop {
+            match io.process(&mut stats, |rx_buf, rx_time, _, _| {
+                assert_eq!(rx_time, 0);
+                assert!(rx_buf.iter().all(|v| *v == Complex::new(1.0, 2.0)));
+                blocks += 1;
+            }) {
+                Ok(()) => break,
+                Err(err) => assert_eq!(err, L1Error::Timeout),
+            }
+        }
+        assert_eq!(blocks, 1);
+        assert!(stats.timeouts >= 2);
+        assert!(matches!(io.process(&mut stats, |_, _, _, _| {}), Err(L1Error::Io(_))));
+        drop(remote.join().unwrap());
+    }
+
+    #[test]
+    fn test_net_udp() {
+        let remote = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let address = remote.local_addr().unwrap().to_string();
+        let mut io = NetIo::new(&NetIoConfig {
+            blocklen: 10000,
+            latency_blocks: 3,
+            fs: 1e6,
+            transport: Transport::Udp,
+            address: &address,
+            local_address: "127.0.0.1:0",
+        }).unwrap();
+
+        // Wait for the empty frame to learn the address of L1
+        let mut bytes = vec![0u8; 65536];
+        let (len, l1_address) = remote.recv_from(&mut bytes).unwrap();
+        assert_eq!(decode_header(&bytes[..len]).unwrap(), (0, 0));
+
+        for i in 0..2 {
+            let mut frame = Vec::new();
+            encode_frame(i * 5_000_000, &[Complex::new(0.5, -0.5); 5000], &mut frame);
+            remote.send_to(&frame, l1_address).unwrap();
+        }
+        io.process(&mut L1Stats::default(), |rx_buf, rx_time, _, tx_time| {
+            assert_eq!(rx_time, 0);
+            assert_eq!(tx_time, 30_000_000);
+            assert!(rx_buf.iter().all(|v| *v == Complex::new(0.5, -0.5)));
+        }).unwrap();
+
+        // Block longer than MAX_FRAME_SAMPLES is split into two frames
+        let mut times = Vec::new();
+        let mut total = 0;
+        while total < 10000 {
+            let len = remote.recv(&mut bytes).unwrap();
+            let (time, n) = decode_header(&bytes[..len]).unwrap();
+            assert_eq!(len, HEADER_LEN + n * 8);
+            times.push(time);
+            total += n;
+        }
+        assert_eq!(times, vec![30_000_000, 30_000_000 + MAX_FRAME_SAMPLES as i64 * 1000]);
+    }
+}
@@ -0,0 +1,401 @@
+//! SigMF recording and playback.
+//!
+//! Receive signal can be read from a SigMF dataset, that is,
+//! a pair of files NAME.sigmf-data and NAME.sigmf-meta.
+//! Transmit signal can be written to another dataset.
+//!
+//! Capture timestamps in the metadata are converted to
+//! nanoseconds since Unix epoch and used as the signal time,
+//! so that bursts received from a recording have the same
+//! timestamps as they would have had when it was captured.
+
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Write};
+use num::Complex;
+use serde_json::{json, Value};
+use super::file::{read_block, SampleFormat};
+use crate::{L1Error, L1Stats};
+use crate::timebase::Timebase;
+
+type StreamType = Complex<f32>;
+
+pub struct SigmfIoConfig<'a> {
+    /// Processing block length in samples
+    pub blocklen: usize,
+    /// Sample rate
+    pub fs: f64,
+    /// Receive center frequency.
+    /// Has to match the frequency of the recording.
+    pub rx_freq: f64,
+    /// Transmit center frequency, written in metadata.
+    pub tx_freq: f64,
+    /// Stop after processing this number of nanoseconds of signal
+    pub stop_time: i64,
+    /// Receive dataset name without the .sigmf-data or .sigmf-meta extension.
+    /// If None, zeros are used as receive signal
+    /// and time starts from current system time.
+    /// Processing stops at the end of the dataset.
+    pub rx_name: Option<&'a str>,
+    /// Transmit dataset name without extension.
+    /// If None, transmit signal is not written.
+    pub tx_name: Option<&'a str>,
+}
+
+/// Map SigMF datatype to sample format.
+fn datatype_format(datatype: &str) -> Option<SampleFormat> {
+    match datatype {
+        "cf32_le" => Some(SampleFormat::Cf32),
+        "ci16_le" => Some(SampleFormat::Cs16),
+        "cu8"     => Some(SampleFormat::Cu8),
+        _ => None,
+    }
+}
+
+/// Days since 1970-01-01 for a date in the proleptic Gregorian calendar.
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let y = if month <= 2 { year - 1 } else { year };
+    let era = y.div_euclid(400);
+    let yoe = y - era * 400;
+    let doy = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146097 + doe - 719468
+}
+
+/// Date (year, month, day) for a number of days since 1970-01-01.
+fn civil_from_days(days: i64) -> (i64, i64, i64) {
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z - era * 146097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    (yoe + era * 400 + if month <= 2 { 1 } else { 0 }, month, day)
+}
+
+/// Parse an ISO 8601 UTC timestamp as used in SigMF,
+/// such as 2023-04-01T12:34:56.789Z,
+/// to nanoseconds since Unix epoch.
+fn parse_datetime(s: &str) -> Option<i64> {
+    let s = s.strip_suffix('Z')?;
+    let (date, time) = s.split_once('T')?;
+    let mut date = date.splitn(3, '-').map(|v| v.parse::<i64>().ok());
+    let (year, month, day) = (date.next()??, date.next()??, date.next()??);
+    let (hms, fraction) = match time.split_once('.') {
+        Some((hms, fraction)) => (hms, fraction),
+        None => (time, ""),
+    };
+    let mut hms = hms.splitn(3, ':').map(|v| v.parse::<i64>().ok());
+    let (hour, minute, second) = (hms.next()??, hms.next()??, hms.next()??);
+    if !(1..=12).contains(&month) || !(1..=31).contains(&day) ||
+       hour > 23 || minute > 59 || second > 60 {
+        return None;
+    }
+    // Nanoseconds from fraction, ignoring digits beyond nanosecond resolution
+    let mut ns: i64 = 0;
+    for (i, c) in fraction.chars().enumerate() {
+        let digit = c.to_digit(10)? as i64;
+        if i < 9 { ns += digit * 10_i64.pow(8 - i as u32); }
+    }
+    let seconds = days_from_civil(year, month, day) * 86400 + hour * 3600 + minute * 60 + second;
+    Some(seconds * 1_000_000_000 + ns)
+}
+
+/// Format nanoseconds since Unix epoch as an ISO 8601 UTC timestamp.
+fn format_datetime(time: i64) -> String {
+    let seconds = time.div_euclid(1_000_000_000);
+    let ns = time.rem_euclid(1_000_000_000);
+    let (year, month, day) = civil_from_days(seconds.div_euclid(86400));
+    let s = seconds.rem_euclid(86400);
+    format!("{:04}-{:02}-{:02}T{:02}:{:02}:{:02}.{:09}Z",
+        year, month, day, s / 3600, s / 60 % 60, s % 60, ns)
+}
+
+/// A capture segment of a receive dataset.
+struct Capture {
+    /// Index of the first sample in the segment
+    sample_start: u64,
+    /// Time of the first sample in the segment
+    time: i64,
+}
+
+/// Receive dataset being read.
+struct SigmfReader {
+    data: BufReader<File>,
+    format: SampleFormat,
+    captures: Vec<Capture>,
+}
+
+impl SigmfReader {
+    fn open(name: &str, fs: f64, rx_freq: f64) -> Result<Self, L1Error> {
+        let meta_filename = format!("{}.sigmf-meta", name);
+        let meta: Value = match std::fs::read_to_string(&meta_filename) {
+            Ok(text) => serde_json::from_str(&text).map_err(|err| L1Error::Io(format!("{}: {}", meta_filename, err)))?,
+            Err(err) => return Err(L1Error::Io(format!("Failed to read {}: {}", meta_filename, err))),
+        };
+        let global = &meta["global"];
+
+        let datatype = global["core:datatype"].as_str().unwrap_or("");
+        let format = datatype_format(datatype).ok_or_else(|| L1Error::Config(
+            format!("{}: unsupported datatype '{}', expected cf32_le, ci16_le or cu8", meta_filename, datatype)))?;
+
+        if let Some(sample_rate) = global["core:sample_rate"].as_f64() {
+            if sample_rate != fs {
+                return Err(L1Error::Config(format!("{}: sample rate {} differs from configured {}", meta_filename, sample_rate, fs)));
+            }
+        }
+
+        let mut segments: Vec<(u64, Option<i64>)> = Vec::new();
+        for capture in meta["captures"].as_array().map(|v| &v[..]).unwrap_or(&[]) {
+            if let Some(freq) = capture["core:frequency"].as_f64() {
+                if freq != rx_freq {
+                    return Err(L1Error::Config(format!("{}: center frequency {} differs from configured {}", meta_filename, freq, rx_freq)));
+                }
+            }
+            let sample_start = capture["core:sample_start"].as_u64().unwrap_or(0);
+            let time = match capture["core:datetime"].as_str() {
+                Some(datetime) => Some(parse_datetime(datetime).ok_or_else(|| L1Error::Io(
+                    format!("{}: invalid datetime '{}'", meta_filename, datetime)))?),
+                None => None,
+            };
+            segments.push((sample_start, time));
+        }
+        // SigMF requires captures in sample order, but do not rely on it
+        segments.sort_by_key(|&(sample_start, _)| sample_start);
+        if let Some(pair) = segments.windows(2).find(|pair| pair[0].0 == pair[1].0) {
+            return Err(L1Error::Config(format!("{}: duplicate capture at sample_start {}", meta_filename, pair[0].0)));
+        }
+
+        let mut captures: Vec<Capture> = Vec::new();
+        for (sample_start, time) in segments {
+            let time = match time {
+                Some(time) => time,
+                // Without a timestamp, continue from the previous segment
+                None => match captures.last() {
+                    Some(prev) => prev.time + samples_to_ns(sample_start - prev.sample_start, fs),
+                    None => 0,
+                },
+            };
+            captures.push(Capture { sample_start, time });
+        }
+        if captures.is_empty() {
+            captures.push(Capture { sample_start: 0, time: 0 });
+        }
+
+        let data_filename = format!("{}.sigmf-data", name);
+        let data = File::open(&data_filename).map_err(|err| L1Error::Io(format!("Failed to open {}: {}", data_filename, err)))?;
+        Ok(Self { data: BufReader::new(data), format, captures })
+    }
+
+    /// Time of a given sample index.
+    fn sample_time(&self, sample: u64, fs: f64) -> i64 {
+        let capture = self.captures.iter().rev()
+            .find(|capture| capture.sample_start <= sample)
+            .unwrap_or(&self.captures[0]);
+        capture.time + samples_to_ns(sample.saturating_sub(capture.sample_start), fs)
+    }
+}
+
+fn samples_to_ns(samples: u64, fs: f64) -> i64 {
+    Timebase::new(fs).samples_to_ns(samples as i64)
+}
+
+/// Write metadata for a transmit dataset.
+fn write_meta(name: &str, fs: f64, tx_freq: f64, start_time: i64) -> Result<(), L1Error> {
+    let meta = json!({
+        "global": {
+            "core:datatype": "cf32_le",
+            "core:sample_rate": fs,
+            "core:version": "1.0.0",
+            "core:description": "TETRA transmit signal",
+        },
+        "captures": [{
+            "core:sample_start": 0,
+            "core:frequency": tx_freq,
+            "core:datetime": format_datetime(start_time),
+        }],
+        "annotations": [],
+    });
+    let filename = format!("{}.sigmf-meta", name);
+    std::fs::write(&filename, serde_json::to_string_pretty(&meta).unwrap())
+        .map_err(|err| L1Error::Io(format!("Failed to write {}: {}", filename, err)))
+}
+
+pub struct SigmfIo {
+    rx: Option<SigmfReader>,
+    tx_data: Option<BufWriter<File>>,
+    fs: f64,
+    // Index of the next sample to be processed
+    sample: u64,
+    // Time of the first processed sample
+    start_time: i64,
+    stop_time: i64,
+    rx_buf: Vec<StreamType>,
+    tx_buf: Vec<StreamType>,
+    // Buffer for file contents in external format
+    filebuf: Vec<u8>,
+}
+
+impl SigmfIo {
+    pub fn new(conf: &SigmfIoConfig) -> Result<Self, L1Error> {
+        let rx = match conf.rx_name {
+            Some(name) => Some(SigmfReader::open(name, conf.fs, conf.rx_freq)?),
+            None => None,
+        };
+        let start_time = match &rx {
+            Some(rx) => rx.sample_time(0, conf.fs),
+            None => std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)
+                .map(|d| d.as_nanos() as i64).unwrap_or(0),
+        };
+        let tx_data = match conf.tx_name {
+            Some(name) => {
+                write_meta(name, conf.fs, conf.tx_freq, start_time)?;
+                let filename = format!("{}.sigmf-data", name);
+                Some(BufWriter::new(File::create(&filename)
+                    .map_err(|err| L1Error::Io(format!("Failed to open {}: {}", filename, err)))?))
+            },
+            None => None,
+        };
+        Ok(Self {
+            rx,
+            tx_data,
+            fs: conf.fs,
+            sample: 0,
+            start_time,
+            stop_time: conf.stop_time,
+            rx_buf: vec![num::zero(); conf.blocklen],
+            tx_buf: vec![num::zero(); conf.blocklen],
+            filebuf: Vec::with_capacity(conf.blocklen * 8),
+        })
+    }
+
+    /// Returns L1Error::EndOfStream at the end of RX dataset
+    /// or after stop time has been reached.
+    pub fn process<F>(&mut self, stats: &mut L1Stats, mut process_signal: F) -> Result<(), L1Error>
+        where F: FnMut(&[Complex<f32>], i64, &mut [Complex<f32>], i64)
+    {
+        let time = if let Some(rx) = &mut self.rx {
+            self.filebuf.resize(self.rx_buf.len() * rx.format.sample_bytes(), 0);
+            match read_block(&mut rx.data, &mut self.filebuf[..], rx.format.sample_bytes(), stats) {
+                Ok(true) => {},
+                Ok(false) =>
+                    return Err(L1Error::EndOfStream),
+                Err(err) =>
+                    return Err(L1Error::Io(format!("Failed to read RX dataset: {}", err))),
+            }
+            rx.format.decode(&self.filebuf[..], &mut self.rx_buf[..]);
+            rx.sample_time(self.sample, self.fs)
+        } else {
+            self.start_time + samples_to_ns(self.sample, self.fs)
+        };
+
+        process_signal(&self.rx_buf[..], time, &mut self.tx_buf[..], time);
+
+        if let Some(tx_data) = &mut self.tx_data {
+            self.filebuf.clear();
+            SampleFormat::Cf32.encode(&self.tx_buf[..], &mut self.filebuf);
+            tx_data.write_all(&self.filebuf[..]).map_err(|err|
+                L1Error::Io(format!("Failed to write TX dataset: {}", err)))?;
+        }
+
+        self.sample += self.rx_buf.len() as u64;
+        if samples_to_ns(self.sample, self.fs) >= self.stop_time { Err(L1Error::EndOfStream) } else { Ok(()) }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_datetime() {
+        let t = parse_datetime("2023-04-01T12:34:56.789Z").unwrap();
+        assert_eq!(t, 1680352496_789_000_000);
+        assert_eq!(format_datetime(t), "2023-04-01T12:34:56.789000000Z");
+        assert_eq!(parse_datetime("1970-01-01T00:00:00Z"), Some(0));
+        assert_eq!(parse_datetime("2000-02-29T23:59:59.000000001Z"), Some(951868799_000_000_001));
+        assert_eq!(format_datetime(-1), "1969-12-31T23:59:59.999999999Z");
+        assert_eq!(parse_datetime("2023-04-01 12:34:56"), None);
+        assert_eq!(parse_datetime("2023-13-01T12:34:56Z"), None);
+    }
+
+    #[test]
+    fn test_sigmf_replay() {
+        let dir = std::env::temp_dir();
+        let rx_name = dir.join(format!("l1_test_rx_{}", std::process::id())).to_str().unwrap().to_string();
+        let tx_name = dir.join(format!("l1_test_tx_{}", std::process::id())).to_str().unwrap().to_string();
+
+        let fs = 1e6;
+        let rx_samples: Vec<StreamType> = (0..40).map(|i| Complex::new(i as f32 / 64.0, -0.5)).collect();
+        let mut bytes = Vec::new();
+        SampleFormat::Cs16.encode(&rx_samples, &mut bytes);
+        std::fs::write(format!("{}.sigmf-data", rx_name), &bytes).unwrap();
+        // Second capture segment starts after a gap of 1 ms
+        std::fs::write(format!("{}.sigmf-meta", rx_name), r#"{
+            "global": { "core:datatype": "ci16_le", "core:sample_rate": 1000000, "core:version": "1.0.0" },
+            "captures": [
+                { "core:sample_start": 0, "core:frequency": 400e6, "core:datetime": "2023-04-01T12:00:00Z" },
+                { "core:sample_start": 20, "core:frequency": 400e6, "core:datetime": "2023-04-01T12:00:00.001020Z" }
+            ],
+            "annotations": []
+        }"#).unwrap();
+
+        let mut io = SigmfIo::new(&SigmfIoConfig {
+            blocklen: 10,
+            fs: fs,
+            rx_freq: 400e6,
+            tx_freq: 410e6,
+            stop_time: 1_000_000_000,
+            rx_name: Some(&rx_name),
+            tx_name: Some(&tx_name),
+        }).unwrap();
+
+        let t0 = parse_datetime("2023-04-01T12:00:00Z").unwrap();
+        let mut received = Vec::new();
+        let mut times = Vec::new();
+        let mut stats = L1Stats::default();
+        while io.process(&mut stats, |rx_buf, rx_time, _, _| {
+            received.extend_from_slice(rx_buf);
+            times.push(rx_time - t0);
+        }).is_ok() {}
+        drop(io);
+
+        assert_eq!(received, rx_samples);
+        assert_eq!(stats, L1Stats::default());
+        assert_eq!(times, vec![0, 10_000, 1_020_000, 1_030_000]);
+
+        let meta: Value = serde_json::from_str(
+            &std::fs::read_to_string(format!("{}.sigmf-meta", tx_name)).unwrap()).unwrap();
+        assert_eq!(meta["global"]["core:datatype"], "cf32_le");
+        assert_eq!(meta["captures"][0]["core:frequency"], 410e6);
+        assert_eq!(meta["captures"][0]["core:datetime"], "2023-04-01T12:00:00.000000000Z");
+        assert_eq!(std::fs::read(format!("{}.sigmf-data", tx_name)).unwrap().len(), 40 * 8);
+
+        // Mismatching center frequency is an error
+        assert!(SigmfReader::open(&rx_name, fs, 401e6).is_err());
+
+        // Captures out of order are sorted, duplicates are rejected
+        std::fs::write(format!("{}.sigmf-meta", rx_name), r#"{
+            "global": { "core:datatype": "ci16_le", "core:sample_rate": 1000000, "core:version": "1.0.0" },
+            "captures": [
+                { "core:sample_start": 20 },
+                { "core:sample_start": 0, "core:datetime": "2023-04-01T12:00:00Z" }
+            ]
+        }"#).unwrap();
+        let reader = SigmfReader::open(&rx_name, fs, 400e6).unwrap();
+        assert_eq!(reader.captures.iter().map(|c| (c.sample_start, c.time - t0)).collect::<Vec<_>>(),
+            vec![(0, 0), (20, 20_000)]);
+        std::fs::write(format!("{}.sigmf-meta", rx_name), r#"{
+            "global": { "core:datatype": "ci16_le", "core:sample_rate": 1000000, "core:version": "1.0.0" },
+            "captures": [ { "core:sample_start": 0 }, { "core:sample_start": 0 } ]
+        }"#).unwrap();
+        assert!(matches!(SigmfReader::open(&rx_name, fs, 400e6), Err(L1Error::Config(_))));
+
+        for name in [&rx_name, &tx_name] {
+            std::fs::remove_file(format!("{}.sigmf-data", name)).unwrap();
+            std::fs::remove_file(format!("{}.sigmf-meta", name)).unwrap();
+        }
+    }
+}
@@ -1,5 +1,7 @@
 use num::Complex;
 use soapysdr;
+use crate::{L1Error, L1Stats};
+use crate::timebase::Timebase;
 
 type StreamType = Complex<f32>;
 
@@ -42,7 +44,20 @@ pub struct SoapyIo {
     dev: soapysdr::Device,
     rx:  soapysdr::RxStream<StreamType>,
     tx:  soapysdr::TxStream<StreamType>,
-    buf: Vec<StreamType>,
+    /// Buffer for received signal
+    rx_buf: Vec<StreamType>,
+    /// Buffer for transmit signal
+    tx_buf: Vec<StreamType>,
+    /// Number of samples read to rx_buf so far.
+    /// Reads may return less than a full block,
+    /// so a block may be completed over several reads.
+    buf_filled: usize,
+    /// Timestamp of the first sample in rx_buf
+    buf_time: i64,
+    /// Timestamp expected for the next read.
+    /// Used to detect gaps in received signal.
+    next_time: Option<i64>,
+    timebase: Timebase,
     /// RX-TX timestamp difference
     latency_time: i64,
 }
@@ -60,20 +75,19 @@ fn convert_args(key_value_pairs: &[(&str, &str)]) -> soapysdr::Args {
 }
 
 /// It is annoying to repeat error handling so do that in a macro.
-/// ? could be used but then it could not print which SoapySDR call failed.
+/// ? could be used but then the error could not tell which SoapySDR call failed.
 macro_rules! soapycheck {
     ($text:literal, $soapysdr_call:expr) => {
         match $soapysdr_call {
             Ok(ret) => { ret },
             Err(err) => {
-                eprintln!("SoapySDR: Failed to {}: {}", $text, err);
-                return Err(err);
+                return Err(L1Error::DeviceOpen(format!("SoapySDR: Failed to {}: {}", $text, err)));
             }
         }
     }
 }
 
-fn soapysdr_setup(conf: &SoapyIoConfig) -> Result<SoapyIo, soapysdr::Error> {
+fn soapysdr_setup(conf: &SoapyIoConfig) -> Result<SoapyIo, L1Error> {
     let dev = soapycheck!("open SoapySDR device",
         soapysdr::Device::new(convert_args(conf.dev_args)));
     soapycheck!("set RX sample rate",
@@ -118,70 +132,110 @@ fn soapysdr_setup(conf: &SoapyIoConfig) -> Result<SoapyIo, soapysdr::Error> {
         dev: dev,
         rx:  rx,
         tx:  tx,
-        buf: vec![num::zero(); conf.blocklen],
-        latency_time: ((conf.blocklen * conf.latency_blocks) as f64 * 1e9 / conf.fs).round() as i64,
+        rx_buf: vec![num::zero(); conf.blocklen],
+        tx_buf: vec![num::zero(); conf.blocklen],
+        buf_filled: 0,
+        buf_time: 0,
+        next_time: None,
+        timebase: Timebase::new(conf.fs),
+        latency_time: Timebase::new(conf.fs).samples_to_ns((conf.blocklen * conf.latency_blocks) as i64),
     })
 }
 
 impl SoapyIo {
-    pub fn new(conf: &SoapyIoConfig) -> Option<Self> {
-        match soapysdr_setup(&conf) {
-            Ok(soapyio) => Some(soapyio),
-            Err(err) => {
-                eprintln!("SoapySDR initialization failed: {}", err);
-                None
-            }
-        }
+    pub fn new(conf: &SoapyIoConfig) -> Result<Self, L1Error> {
+        soapysdr_setup(&conf)
     }
 
-    /// Returns Some(()) on success, None on error.
-    /// Maybe some proper error type would be better.
-    pub fn process<F>(&mut self, mut process_signal: F) -> Option<()>
-        where F: FnMut(&mut [Complex<f32>], i64, i64)
+    /// Read a full block of received signal, process it
+    /// and write the transmit signal.
+    ///
+    /// Returns an error if reading or writing the stream failed.
+    /// Processing can continue after a recoverable error,
+    /// see L1Error::is_recoverable. Samples of a partially read
+    /// block are kept, so the block is completed on the next call.
+    pub fn process<F>(&mut self, stats: &mut L1Stats, mut process_signal: F) -> Result<(), L1Error>
+        where F: FnMut(&[Complex<f32>], i64, &mut [Complex<f32>], i64)
     {
-        match self.rx.read_ext(&mut [&mut self.buf[..]], soapysdr::StreamFlags::default(), None, 100000) {
-            Ok(result) => {
-                if result.len != self.buf.len() {
-                    eprintln!("Warning: expected {} samples, read {}", self.buf.len(), result.len);
-                }
-                let buf_slice = &mut self.buf[0..result.len];
-                if let Some(time) = result.time {
-                    let tx_time = time + self.latency_time;
-                    process_signal(buf_slice, time, tx_time);
-                    match self.tx.write_all(&[buf_slice], Some(tx_time), false, 100000) {
-                        Ok(_) => Some(()),
-                        Err(err) => {
-                            eprintln!("Stream write error: {}", err);
-                            if error_is_recoverable(&err) { Some(()) } else { None }
-                        }
-                    }
-                } else {
-                    eprintln!("Radios without timestamp support are not currently supported.");
-                    None
+        while self.buf_filled < self.rx_buf.len() {
+            self.read(stats)?;
+        }
+        self.buf_filled = 0;
+
+        let rx_time = self.buf_time;
+        let tx_time = rx_time + self.latency_time;
+        process_signal(&self.rx_buf[..], rx_time, &mut self.tx_buf[..], tx_time);
+        self.tx.write_all(&[&self.tx_buf[..]], Some(tx_time), false, 100000)
+            .map_err(|err| {
+                match err.code {
+                    soapysdr::ErrorCode::TimeError => stats.late_tx += 1,
+                    _ => count_error(stats, &err),
                 }
-            },
-            Err(err) => {
-                eprintln!("Stream read error: {}", err);
-                if error_is_recoverable(&err) { Some(()) } else { None }
+                convert_error("Stream write error", err)
+            })
+    }
+
+    /// Read samples to fill the rest of the block.
+    /// If the timestamp shows that samples are missing since the previous
+    /// read, for example because of an overflow, the partially read block
+    /// is discarded and a new block starts from the samples just read.
+    fn read(&mut self, stats: &mut L1Stats) -> Result<(), L1Error> {
+        let wanted = self.rx_buf.len() - self.buf_filled;
+        let result = self.rx.read_ext(&mut [&mut self.rx_buf[self.buf_filled..]], soapysdr::StreamFlags::default(), None, 100000)
+            .map_err(|err| {
+                count_error(stats, &err);
+                convert_error("Stream read error", err)
+            })?;
+        let time = result.time.ok_or_else(||
+            L1Error::Stream("Radios without timestamp support are not currently supported.".to_string()))?;
+        if result.len < wanted {
+            stats.short_reads += 1;
+        }
+
+        if let Some(expected) = self.next_time {
+            if self.timebase.is_discontinuous(time, expected) {
+                let missing = self.timebase.ns_to_samples(time - expected);
+                log_limited!(crate::log::L1LogLevel::Warning,
+                    "Gap of {} samples in received signal, discarding {} samples to resynchronize",
+                    missing, self.buf_filled);
+                stats.dropped_samples += self.buf_filled as u64 + missing.max(0) as u64;
+                self.rx_buf.copy_within(self.buf_filled .. self.buf_filled + result.len, 0);
+                self.buf_filled = 0;
             }
         }
+        if self.buf_filled == 0 {
+            self.buf_time = time;
+        }
+        self.buf_filled += result.len;
+        self.next_time = Some(time + self.timebase.samples_to_ns(result.len as i64));
+        Ok(())
+    }
+}
+
+fn count_error(stats: &mut L1Stats, err: &soapysdr::Error) {
+    match err.code {
+        soapysdr::ErrorCode::Timeout    => stats.timeouts += 1,
+        soapysdr::ErrorCode::Overflow   => stats.overflows += 1,
+        soapysdr::ErrorCode::Underflow  => stats.underflows += 1,
+        soapysdr::ErrorCode::TimeError  => stats.time_errors += 1,
+        soapysdr::ErrorCode::Corruption => stats.corruptions += 1,
+        _ => {},
     }
 }
 
-fn error_is_recoverable(err: &soapysdr::Error) -> bool {
+fn convert_error(text: &str, err: soapysdr::Error) -> L1Error {
     match err.code {
         // These errors could be caused by a momentary scheduling latency,
         // lost packet on network or bus or something like that.
-        // SDR might recover from those so just "ignore" the error
-        // and continue reading the stream on next call.
-        soapysdr::ErrorCode::Timeout |
-        soapysdr::ErrorCode::Corruption |
-        soapysdr::ErrorCode::Overflow |
-        soapysdr::ErrorCode::TimeError |
-        soapysdr::ErrorCode::Underflow => true,
+        // SDR might recover from those, so they are returned as
+        // recoverable errors and the stream can be read again on next call.
+        soapysdr::ErrorCode::Timeout => L1Error::Timeout,
+        soapysdr::ErrorCode::Overflow => L1Error::Overflow,
+        soapysdr::ErrorCode::Underflow => L1Error::Underflow,
+        soapysdr::ErrorCode::Corruption => L1Error::Corruption,
+        soapysdr::ErrorCode::TimeError => L1Error::TimeError,
         // Others might signal a bigger problem, such as disconnected or
-        // broken SDR device, so return an error for these
-        // and make the program stop.
-        _ => false,
+        // broken SDR device.
+        _ => L1Error::Stream(format!("{}: {}", text, err)),
     }
 }
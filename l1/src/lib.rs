@@ -1,8 +1,13 @@
-use std::ffi::{c_int, c_void};
+use std::ffi::{c_char, c_int, c_void, CStr};
+
+#[macro_use]
+pub mod log;
 
 pub mod slot;
 pub use slot::SlotNumber;
 
+pub mod timebase;
+
 pub mod burst;
 pub use burst::*;
 
@@ -11,6 +16,15 @@ use dsp::L1Dsp;
 
 pub mod io;
 
+pub mod config;
+use config::{CarrierConfig, L1Config};
+
+pub mod error;
+pub use error::{L1Error, L1ErrorCode};
+
+pub mod stats;
+pub use stats::L1Stats;
+
 #[repr(C)]
 pub struct L1RxCommands {
     /// Slot timestamp corresponding to timing_slot.
@@ -24,6 +38,16 @@ pub struct L1RxCommands {
     // TODO: RX mode setting
 }
 
+impl Default for L1RxCommands {
+    fn default() -> Self {
+        Self {
+            timing_time: 0,
+            timing_slot: SlotNumber::new(1, 1, 1),
+            set_timing: false,
+        }
+    }
+}
+
 #[repr(C)]
 pub struct L1TxCommands {
     /// Slot timestamp corresponding to timing_slot
@@ -36,6 +60,16 @@ pub struct L1TxCommands {
     pub set_timing: bool,
 }
 
+impl Default for L1TxCommands {
+    fn default() -> Self {
+        Self {
+            timing_time: 0,
+            timing_slot: SlotNumber::new(1, 1, 1),
+            set_timing: false,
+        }
+    }
+}
+
 #[repr(C)]
 pub struct L1Callbacks {
     /// C function to process received burst(s).
@@ -61,6 +95,9 @@ pub struct L1Callbacks {
     /// Argument passed to tx_burst.
     pub tx_burst_arg: *mut c_void,
     /// Get commands for a receive carrier.
+    /// Called at the beginning of each slot for each carrier.
+    /// Commands are initialized to default values before the call,
+    /// so only the ones to be used need to be set.
     pub rx_cmd: extern "C" fn(
         arg: *mut c_void,
         carrier: i32,
@@ -69,6 +106,9 @@ pub struct L1Callbacks {
     /// Argument passed to rx_cmd.
     pub rx_cmd_arg: *mut c_void,
     /// Get commands for a transmit carrier.
+    /// Called before beginning each slot for each carrier.
+    /// Commands are initialized to default values before the call,
+    /// so only the ones to be used need to be set.
     pub tx_cmd: extern "C" fn(
         arg: *mut c_void,
         carrier: i32,
@@ -84,60 +124,70 @@ pub struct L1 {
 }
 
 impl L1 {
-    fn new() -> Option<Self> {
-        let fs: f64 = 1.8e6;
-        // 4 ms block length
-        let blocklen = (fs * 0.004).round() as usize;
-        // TODO: add L1 configuration
-        let test_to_file = false;
-        Some(Self {
-            radio: if test_to_file {
-                io::RadioIo::new(&io::RadioIoConfig::File(&io::file::FileIoConfig {
-                    blocklen: blocklen,
-                    fs: fs,
-                    stop_time: 1e9 as i64,
-                    tx_filename: "test_out.raw",
-                }))?
-            } else {
-                io::RadioIo::new(&io::RadioIoConfig::Soapy(&io::soapy::SoapyIoConfig {
-                    blocklen: blocklen,
-                    latency_blocks: 3,
-                    fs: fs,
-                    rx_freq: 434e6,
-                    tx_freq: 434e6,
-                    rx_chan: 0,
-                    tx_chan: 0,
-                    rx_ant:  "LNAL",
-                    tx_ant:  "BAND1",
-                    rx_gain: &[(None, 50.0)],
-                    tx_gain: &[
-                        (Some("PAD" ), 52.0),
-                        (Some("IAMP"), 0.0),
-                    ],
-                    dev_args: &[("driver", "lime")],
-                    rx_args: &[],
-                    tx_args: &[],
-                }))?
-            },
-            dsp: L1Dsp::new(fs),
+    fn new(conf: &L1Config) -> Result<Self, L1Error> {
+        // Set up signal processing first, so that configuration errors
+        // are found before opening the radio.
+        let dsp = L1Dsp::new(conf).map_err(L1Error::Config)?;
+        Ok(Self {
+            radio: conf.open_radio()?,
+            dsp: dsp,
         })
     }
 
     fn process(&mut self,
         callbacks: &L1Callbacks,
-    ) -> Option<()> {
-        self.radio.process(|buf, rx_time, tx_time| {
-            self.dsp.process(buf, rx_time, tx_time, callbacks)
+    ) -> Result<(), L1Error> {
+        self.radio.process(|rx_buf, rx_time, tx_buf, tx_time| {
+            self.dsp.process(rx_buf, rx_time, tx_buf, tx_time, callbacks)
         })
     }
 }
 
+/// Read configuration from a file given as a C string,
+/// or use default configuration if it is NULL.
+fn load_config(config_path: *const c_char) -> Result<L1Config, L1Error> {
+    if config_path.is_null() {
+        Ok(L1Config::default())
+    } else {
+        let path = unsafe { CStr::from_ptr(config_path) }.to_string_lossy();
+        L1Config::from_file(&path).map_err(L1Error::Config)
+    }
+}
+
+/// Initialize L1.
+/// config_path is the path to a configuration file.
+/// If it is NULL, default configuration is used.
+/// Returns NULL on failure.
 #[no_mangle]
 pub extern "C" fn l1_init(
+    config_path: *const c_char,
 ) -> *mut L1 {
-    match L1::new() {
-        Some(l1) => Box::into_raw(Box::<L1>::new(l1)),
-        None => core::ptr::null_mut()
+    match load_config(config_path).and_then(|conf| L1::new(&conf)) {
+        Ok(l1) => Box::into_raw(Box::<L1>::new(l1)),
+        Err(err) => {
+            log_error!("L1 initialization failed: {}", err);
+            core::ptr::null_mut()
+        }
+    }
+}
+
+/// Check configuration without opening the radio.
+/// config_path is given the same way as for l1_init.
+/// The resulting frequency plan is logged at info level.
+/// Returns 0 if configuration is valid or L1_ERROR_CODE_CONFIG if not.
+#[no_mangle]
+pub extern "C" fn l1_check_config(
+    config_path: *const c_char,
+) -> c_int {
+    match load_config(config_path).and_then(|conf| L1Dsp::new(&conf).map_err(L1Error::Config)) {
+        Ok(dsp) => {
+            log_info!("Frequency plan:\n{}", dsp.frequency_plan());
+            L1ErrorCode::Ok as c_int
+        },
+        Err(err) => {
+            log_error!("{}", err);
+            err.into()
+        }
     }
 }
 
@@ -153,16 +203,108 @@ pub extern "C" fn l1_free(
     }
 }
 
+/// Add a carrier.
+/// This must not be called from callbacks, only between
+/// calls to l1_process. The carrier is then processed
+/// starting from the next call to l1_process.
+/// Returns the carrier index passed to callbacks
+/// or L1_ERROR_CODE_CONFIG if carrier configuration is invalid
+/// or either pointer is NULL.
+///
+/// # Safety
+/// l1 shall be NULL or returned by l1_init and not yet freed.
+/// carrier shall be NULL or point to a valid CarrierConfig.
+#[no_mangle]
+pub unsafe extern "C" fn l1_add_carrier(
+    l1: *mut L1,
+    carrier: *const CarrierConfig,
+) -> c_int {
+    let (Some(l1_), Some(carrier_)) = (unsafe { l1.as_mut() }, unsafe { carrier.as_ref() }) else {
+        return L1ErrorCode::Config as c_int;
+    };
+    match l1_.dsp.add_carrier(carrier_) {
+        Ok(id) => id,
+        Err(err) => {
+            let err = L1Error::Config(err);
+            log_error!("{}", err);
+            err.into()
+        }
+    }
+}
+
+/// Remove a carrier with a given carrier index.
+/// This must not be called from callbacks, only between
+/// calls to l1_process.
+/// Returns 0 on success or L1_ERROR_CODE_CONFIG if there was no such carrier
+/// or l1 is NULL.
+///
+/// # Safety
+/// l1 shall be NULL or returned by l1_init and not yet freed.
+#[no_mangle]
+pub unsafe extern "C" fn l1_remove_carrier(
+    l1: *mut L1,
+    carrier: i32,
+) -> c_int {
+    let Some(l1_) = (unsafe { l1.as_mut() }) else {
+        return L1ErrorCode::Config as c_int;
+    };
+    if l1_.dsp.remove_carrier(carrier) {
+        L1ErrorCode::Ok as c_int
+    } else {
+        L1ErrorCode::Config as c_int
+    }
+}
+
+/// Get statistics counters of an L1 instance.
+/// This must not be called from callbacks, only between
+/// calls to l1_process.
+/// Returns all counters as zero if l1 is NULL.
+/// Radio errors and timeouts are only counted by the soapy and net
+/// backends. File and SigMF replay count a partial block at the end
+/// of input. Loopback and channel simulation only count blocks.
+///
+/// # Safety
+/// l1 shall be NULL or returned by l1_init and not yet freed.
+#[no_mangle]
+pub unsafe extern "C" fn l1_get_stats(
+    l1: *const L1,
+) -> L1Stats {
+    match unsafe { l1.as_ref() } {
+        Some(l1_) => l1_.radio.stats(),
+        None => L1Stats::default(),
+    }
+}
+
 /// C wrapper for L1::process.
-/// Returns 0 on success, negative number on failure.
+/// Returns 0 on success, negative L1ErrorCode on failure.
+/// Processing can continue after errors for which
+/// l1_error_is_recoverable returns true.
+/// L1_ERROR_CODE_END_OF_STREAM is returned when a file or
+/// simulated radio has reached its end.
+/// L1_ERROR_CODE_CONFIG is returned if l1 is NULL.
+///
+/// # Safety
+/// l1 shall be NULL or returned by l1_init and not yet freed.
+/// Callback functions shall be safe to call with their arguments.
 #[no_mangle]
-pub extern "C" fn l1_process(
+pub unsafe extern "C" fn l1_process(
     l1: *mut L1,
     callbacks: L1Callbacks,
 ) -> c_int {
-    let l1_ = unsafe { l1.as_mut().expect("l1 shall not be NULL") };
+    let Some(l1_) = (unsafe { l1.as_mut() }) else {
+        return L1ErrorCode::Config as c_int;
+    };
     match l1_.process(&callbacks) {
-        Some(()) => 0,
-        _ => -1
+        Ok(()) => L1ErrorCode::Ok as c_int,
+        Err(err) => {
+            if err.is_recoverable() {
+                log_limited!(log::L1LogLevel::Warning, "{}", err);
+            } else if err != L1Error::EndOfStream {
+                log_error!("{}", err);
+            } else {
+                log_info!("{}", err);
+            }
+            err.into()
+        }
     }
 }
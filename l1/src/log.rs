@@ -0,0 +1,276 @@
+//! Logging of L1 diagnostics.
+//!
+//! Messages are written to stderr by default. An application can
+//! register a callback with l1_set_log_callback to route them
+//! into its own logging system instead.
+//!
+//! Use the log_error!, log_warning!, log_info! and log_debug! macros
+//! to log a message. Messages that could be repeated for every
+//! processing block should use log_limited!, so that a persistent
+//! problem does not flood the log from the realtime thread.
+
+use std::ffi::{c_char, c_void, CStr, CString};
+use std::fmt;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicU8, Ordering};
+use std::time::{Duration, Instant};
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Eq, PartialEq, PartialOrd, Ord)]
+pub enum L1LogLevel {
+    Error = 0,
+    Warning = 1,
+    Info = 2,
+    Debug = 3,
+}
+
+impl L1LogLevel {
+    fn name(self) -> &'static str {
+        match self {
+            L1LogLevel::Error   => "error",
+            L1LogLevel::Warning => "warning",
+            L1LogLevel::Info    => "info",
+            L1LogLevel::Debug   => "debug",
+        }
+    }
+}
+
+/// C function receiving log messages.
+/// message is a NUL terminated string valid only during the call.
+pub type L1LogCallback = extern "C" fn(
+    arg: *mut c_void,
+    level: L1LogLevel,
+    message: *const c_char,
+);
+
+struct Logger {
+    callback: Option<L1LogCallback>,
+    callback_arg: *mut c_void,
+}
+
+// Callback argument is only passed back to the callback,
+// which the application has to make safe to call from any thread.
+unsafe impl Send for Logger {}
+
+static LOGGER: Mutex<Logger> = Mutex::new(Logger {
+    callback: None,
+    callback_arg: std::ptr::null_mut(),
+});
+
+/// Messages above this level are discarded.
+/// Kept outside LOGGER so that discarded messages
+/// do not have to take the lock.
+static MAX_LEVEL: AtomicU8 = AtomicU8::new(L1LogLevel::Info as u8);
+
+/// Check whether messages of a level would be logged,
+/// to avoid formatting messages that would be discarded.
+pub fn enabled(level: L1LogLevel) -> bool {
+    level as u8 <= MAX_LEVEL.load(Ordering::Relaxed)
+}
+
+/// Log a message. Usually called through the logging macros.
+pub fn log(level: L1LogLevel, args: fmt::Arguments) {
+    if !enabled(level) {
+        return;
+    }
+    let (callback, callback_arg) = {
+        let logger = LOGGER.lock().unwrap();
+        (logger.callback, logger.callback_arg)
+    };
+    // Lock is not held while calling the callback,
+    // so the callback may call l1_log.
+    match callback {
+        Some(callback) => {
+            // Messages should not contain NUL, but replace it just in case
+            // instead of losing the message.
+            let message = CString::new(args.to_string().replace('\0', " ")).unwrap();
+            callback(callback_arg, level, message.as_ptr());
+        },
+        None => eprintln!("L1 {}: {}", level.name(), args),
+    }
+}
+
+/// Rate limiter for a message logged at a single place.
+pub struct RateLimit(Mutex<RateLimitState>);
+
+struct RateLimitState {
+    window_start: Option<Instant>,
+    count: u32,
+    suppressed: u32,
+}
+
+/// Length of rate limiting window
+const RATE_LIMIT_WINDOW: Duration = Duration::from_secs(1);
+/// Number of messages allowed within a window
+const RATE_LIMIT_MESSAGES: u32 = 5;
+
+impl RateLimit {
+    pub const fn new() -> Self {
+        Self(Mutex::new(RateLimitState { window_start: None, count: 0, suppressed: 0 }))
+    }
+
+    /// Log a message unless too many have been logged recently.
+    /// Number of suppressed messages is reported with the next
+    /// message after the window ends. If the message does not
+    /// occur again, suppressed messages are not reported.
+    pub fn log(&self, level: L1LogLevel, args: fmt::Arguments) {
+        self.log_at(Instant::now(), level, args)
+    }
+
+    fn log_at(&self, now: Instant, level: L1LogLevel, args: fmt::Arguments) {
+        if !enabled(level) {
+            return;
+        }
+        // Lock is released before logging, so that a log callback
+        // does not block other threads using the same limit.
+        let suppressed = {
+            let mut state = self.0.lock().unwrap();
+            let window_ended = state.window_start.is_none_or(|start| now.duration_since(start) >= RATE_LIMIT_WINDOW);
+            if window_ended {
+                state.window_start = Some(now);
+                state.count = 0;
+            }
+            if state.count >= RATE_LIMIT_MESSAGES {
+                state.suppressed += 1;
+                return;
+            }
+            state.count += 1;
+            std::mem::take(&mut state.suppressed)
+        };
+        if suppressed > 0 {
+            log(level, format_args!("{} ({} similar messages suppressed)", args, suppressed));
+        } else {
+            log(level, args);
+        }
+    }
+}
+
+impl Default for RateLimit {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[macro_export]
+macro_rules! log_error {
+    ($($arg:tt)*) => { $crate::log::log($crate::log::L1LogLevel::Error, format_args!($($arg)*)) }
+}
+
+#[macro_export]
+macro_rules! log_warning {
+    ($($arg:tt)*) => { $crate::log::log($crate::log::L1LogLevel::Warning, format_args!($($arg)*)) }
+}
+
+#[macro_export]
+macro_rules! log_info {
+    ($($arg:tt)*) => { $crate::log::log($crate::log::L1LogLevel::Info, format_args!($($arg)*)) }
+}
+
+#[macro_export]
+macro_rules! log_debug {
+    ($($arg:tt)*) => { $crate::log::log($crate::log::L1LogLevel::Debug, format_args!($($arg)*)) }
+}
+
+/// Log a message with rate limiting.
+/// Each place where the macro is used is limited separately.
+#[macro_export]
+macro_rules! log_limited {
+    ($level:expr, $($arg:tt)*) => {{
+        static LIMIT: $crate::log::RateLimit = $crate::log::RateLimit::new();
+        LIMIT.log($level, format_args!($($arg)*))
+    }}
+}
+
+/// Set a callback for log messages.
+/// If callback is NULL, messages are written to stderr.
+/// The callback may be called from any thread using L1 functions,
+/// including the thread running l1_process.
+#[no_mangle]
+pub extern "C" fn l1_set_log_callback(
+    callback: Option<L1LogCallback>,
+    arg: *mut c_void,
+) {
+    let mut logger = LOGGER.lock().unwrap();
+    logger.callback = callback;
+    logger.callback_arg = arg;
+}
+
+/// Set the most verbose level of messages to log.
+/// Default is L1_LOG_LEVEL_INFO.
+#[no_mangle]
+pub extern "C" fn l1_set_log_level(
+    level: L1LogLevel,
+) {
+    MAX_LEVEL.store(level as u8, Ordering::Relaxed);
+}
+
+/// Returns true if messages of a level would be logged.
+/// Use this to skip formatting messages that would be discarded,
+/// for example in callbacks called for every slot.
+#[no_mangle]
+pub extern "C" fn l1_log_enabled(
+    level: L1LogLevel,
+) -> bool {
+    enabled(level)
+}
+
+/// Log a message through L1 logging,
+/// so that other layers can use the same log destination.
+///
+/// # Safety
+///
+/// message must be NULL or point to a NUL terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn l1_log(
+    level: L1LogLevel,
+    message: *const c_char,
+) {
+    if message.is_null() || !enabled(level) {
+        return;
+    }
+    let message = unsafe { CStr::from_ptr(message) }.to_string_lossy();
+    log(level, format_args!("{}", message));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    static MESSAGES: Mutex<Vec<(L1LogLevel, String)>> = Mutex::new(Vec::new());
+
+    extern "C" fn test_callback(_arg: *mut c_void, level: L1LogLevel, message: *const c_char) {
+        let message = unsafe { CStr::from_ptr(message) }.to_str().unwrap().to_string();
+        MESSAGES.lock().unwrap().push((level, message));
+    }
+
+    // Logger is global, so everything using it is tested in one test.
+    #[test]
+    fn test_log() {
+        l1_set_log_callback(Some(test_callback), std::ptr::null_mut());
+        l1_set_log_level(L1LogLevel::Info);
+
+        log_warning!("test: read {} samples", 5);
+        log_debug!("test: not shown");
+        unsafe { l1_log(L1LogLevel::Info, c"test: from C".as_ptr()) };
+        assert!(l1_log_enabled(L1LogLevel::Info));
+        assert!(!l1_log_enabled(L1LogLevel::Debug));
+
+        let limit = RateLimit::new();
+        let start = Instant::now();
+        for i in 0..8 {
+            limit.log_at(start + Duration::from_millis(i * 10), L1LogLevel::Warning, format_args!("test: overflow"));
+        }
+        limit.log_at(start + Duration::from_millis(1500), L1LogLevel::Warning, format_args!("test: overflow"));
+
+        l1_set_log_callback(None, std::ptr::null_mut());
+
+        // Other tests running in parallel may log too,
+        // so only check messages from this test.
+        let messages: Vec<(L1LogLevel, String)> = MESSAGES.lock().unwrap().iter()
+            .filter(|(_, m)| m.starts_with("test: ")).cloned().collect();
+        assert_eq!(messages[0], (L1LogLevel::Warning, "test: read 5 samples".to_string()));
+        assert_eq!(messages[1], (L1LogLevel::Info, "test: from C".to_string()));
+        assert_eq!(messages.len(), 2 + 5 + 1);
+        assert_eq!(messages[7].1, "test: overflow (3 similar messages suppressed)");
+    }
+}
@@ -0,0 +1,28 @@
+//! Counters for monitoring radio health.
+
+/// Counters of processed blocks and of problems with the radio stream.
+/// All counters are cumulative since L1 was initialized,
+/// so monitoring should look at how much they change over time.
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+pub struct L1Stats {
+    /// Number of processed signal blocks
+    pub blocks: u64,
+    /// Timeouts waiting for received samples
+    pub timeouts: u64,
+    /// Receive overflows reported by the radio
+    pub overflows: u64,
+    /// Transmit underflows reported by the radio
+    pub underflows: u64,
+    /// Receive time errors reported by the radio
+    pub time_errors: u64,
+    /// Corrupted received data reported by the radio
+    pub corruptions: u64,
+    /// Reads returning fewer samples than a full block
+    pub short_reads: u64,
+    /// Received samples missing from the stream
+    /// or discarded to resynchronize to it
+    pub dropped_samples: u64,
+    /// Transmit writes rejected because their time had already passed
+    pub late_tx: u64,
+}
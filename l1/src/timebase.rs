@@ -0,0 +1,72 @@
+//! Conversion between sample counts and timestamps.
+//!
+//! Timestamps are integer nanoseconds, but a sample period is usually
+//! not an integer number of nanoseconds. Adding a rounded sample period
+//! for every sample or block would make the rounding errors accumulate,
+//! so time is instead computed from a sample count since a reference
+//! timestamp, using exact integer arithmetic.
+
+/// Sample rate as an exact rational number of samples per nanosecond.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct Timebase {
+    /// Sample rate in millihertz
+    fs_mhz: i128,
+}
+
+impl Timebase {
+    /// Sample rate is rounded to the nearest millihertz.
+    pub fn new(fs: f64) -> Self {
+        assert!(fs > 0.0);
+        Self { fs_mhz: (fs * 1000.0).round() as i128 }
+    }
+
+    /// Duration of a number of samples in nanoseconds,
+    /// rounded to the nearest nanosecond.
+    pub fn samples_to_ns(&self, samples: i64) -> i64 {
+        div_round(samples as i128 * 1_000_000_000_000, self.fs_mhz) as i64
+    }
+
+    /// Number of samples in a duration of nanoseconds,
+    /// rounded to the nearest sample.
+    pub fn ns_to_samples(&self, ns: i64) -> i64 {
+        div_round(ns as i128 * self.fs_mhz, 1_000_000_000_000) as i64
+    }
+
+    /// Whether timestamps differ by more than half a sample period,
+    /// meaning that there is a discontinuity between them.
+    pub fn is_discontinuous(&self, time: i64, expected: i64) -> bool {
+        self.ns_to_samples(time - expected) != 0
+    }
+}
+
+/// Division rounding to nearest, with halves rounded up.
+/// Divisor shall be positive.
+fn div_round(a: i128, b: i128) -> i128 {
+    (a * 2 + b).div_euclid(b * 2)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_timebase() {
+        let tb = Timebase::new(1.8e6);
+        assert_eq!(tb.samples_to_ns(1), 556);
+        assert_eq!(tb.samples_to_ns(-1), -556);
+        assert_eq!(tb.samples_to_ns(7200), 4_000_000);
+        // A day of samples without any accumulated error
+        let day = 1_800_000 * 86400;
+        assert_eq!(tb.samples_to_ns(day), 86400_000_000_000);
+        assert_eq!(tb.ns_to_samples(86400_000_000_000), day);
+        assert_eq!(tb.ns_to_samples(277), 0);
+        assert_eq!(tb.ns_to_samples(278), 1);
+        assert_eq!(tb.ns_to_samples(-278), -1);
+        assert!(!tb.is_discontinuous(1000, 1200));
+        assert!(tb.is_discontinuous(1000, 1300));
+        // Modem sample rate
+        let tb = Timebase::new(72000.0);
+        assert_eq!(tb.samples_to_ns(9), 125_000);
+        assert_eq!(tb.samples_to_ns(72000 * 3600), 3600_000_000_000);
+    }
+}
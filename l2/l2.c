@@ -17,14 +17,22 @@ struct L2 *l2_init(void)
 void l2_rx_callback(void *arg, int32_t carrier, struct SlotNumber slot, int64_t slot_time, const struct RxBurst *burst)
 {
 	struct L2 *l2 = arg;
-	fprintf(stderr, "RX callback for carrier %2d slot %2d,%2d,%2d\n", carrier, slot.multiframe, slot.frame, slot.timeslot);
+	if (l1_log_enabled(L1_LOG_LEVEL_DEBUG)) {
+		char msg[80];
+		snprintf(msg, sizeof(msg), "RX callback for carrier %2d slot %2d,%2d,%2d", carrier, slot.multiframe, slot.frame, slot.timeslot);
+		l1_log(L1_LOG_LEVEL_DEBUG, msg);
+	}
 	// TODO
 }
 
 void l2_tx_callback(void *arg, int32_t carrier, struct SlotNumber slot, int64_t slot_time, struct TxBurst *burst)
 {
 	struct L2 *l2 = arg;
-	fprintf(stderr, "TX callback for carrier %2d slot %2d,%2d,%2d\n", carrier, slot.multiframe, slot.frame, slot.timeslot);
+	if (l1_log_enabled(L1_LOG_LEVEL_DEBUG)) {
+		char msg[80];
+		snprintf(msg, sizeof(msg), "TX callback for carrier %2d slot %2d,%2d,%2d", carrier, slot.multiframe, slot.frame, slot.timeslot);
+		l1_log(L1_LOG_LEVEL_DEBUG, msg);
+	}
 
 	// Make some TX burst for testing.
 	// Transmit random bits to see if spectrum looks correct.
@@ -34,3 +42,17 @@ void l2_tx_callback(void *arg, int32_t carrier, struct SlotNumber slot, int64_t
 		burst->dl[i] = (rand() >> 30) & 1;
 	}
 }
+
+void l2_rx_cmd_callback(void *arg, int32_t carrier, struct L1RxCommands *commands)
+{
+	struct L2 *l2 = arg;
+	(void)carrier; (void)commands;
+	// TODO
+}
+
+void l2_tx_cmd_callback(void *arg, int32_t carrier, struct L1TxCommands *commands)
+{
+	struct L2 *l2 = arg;
+	(void)carrier; (void)commands;
+	// TODO
+}
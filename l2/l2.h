@@ -15,4 +15,12 @@ void l2_rx_callback(void *arg, int32_t carrier, struct SlotNumber slot, int64_t
 // If a burst should be transmitted in the slot, write it to burst.
 void l2_tx_callback(void *arg, int32_t carrier, struct SlotNumber slot, int64_t slot_time, struct TxBurst *burst);
 
+// Called by L1 at the beginning of each slot.
+// Commands are initialized to defaults, so only set the ones needed.
+void l2_rx_cmd_callback(void *arg, int32_t carrier, struct L1RxCommands *commands);
+
+// Called by L1 before beginning each slot.
+// Commands are initialized to defaults, so only set the ones needed.
+void l2_tx_cmd_callback(void *arg, int32_t carrier, struct L1TxCommands *commands);
+
 #endif
@@ -2,10 +2,14 @@
 #include "l2.h"
 #include <stdlib.h>
 #include <stdio.h>
+#include <string.h>
 #include <signal.h>
 
 volatile bool running = 1;
 
+// Path to L1 configuration file, NULL to use default configuration
+static const char *l1_config_path = NULL;
+
 static void sighandler(int sig)
 {
 	(void)sig;
@@ -27,7 +31,7 @@ static void setup_sighandler(void)
 void *realtime_thread(void *arg)
 {
 	struct L2 *l2 = arg;
-	struct L1 *l1 = l1_init();
+	struct L1 *l1 = l1_init(l1_config_path);
 	if (l1 == NULL) {
 		running = 0;
 		return NULL;
@@ -40,22 +44,60 @@ void *realtime_thread(void *arg)
 				.rx_burst_arg = l2,
 				.tx_burst = l2_tx_callback,
 				.tx_burst_arg = l2,
-				// TODO: command callbacks
+				.rx_cmd = l2_rx_cmd_callback,
+				.rx_cmd_arg = l2,
+				.tx_cmd = l2_tx_cmd_callback,
+				.tx_cmd_arg = l2,
 			}
 		);
+		if (ret == L1_ERROR_CODE_END_OF_STREAM) {
+			running = 0;
+			break;
+		}
+		// Errors have already been logged by L1
 		if (ret < 0) {
-			fprintf(stderr, "l1_process error: %d\n", ret);
+			if (l1_error_is_recoverable(ret))
+				continue;
 			running = 0;
 			break;
 		}
 	}
+	struct L1Stats stats = l1_get_stats(l1);
+	fprintf(stderr,
+		"Processed %llu blocks: %llu timeouts, %llu overflows, %llu underflows, "
+		"%llu time errors, %llu corruptions, %llu short reads, "
+		"%llu dropped samples, %llu late TX writes\n",
+		(unsigned long long)stats.blocks,
+		(unsigned long long)stats.timeouts,
+		(unsigned long long)stats.overflows,
+		(unsigned long long)stats.underflows,
+		(unsigned long long)stats.time_errors,
+		(unsigned long long)stats.corruptions,
+		(unsigned long long)stats.short_reads,
+		(unsigned long long)stats.dropped_samples,
+		(unsigned long long)stats.late_tx);
 	l1_free(l1);
 	return NULL;
 }
 
 int main(int argc, char *argv[])
 {
-	(void)argc; (void)argv;
+	// With -n, only check configuration and print the frequency plan
+	// without opening the radio.
+	bool dry_run = false;
+	int arg = 1;
+	if (arg < argc && strcmp(argv[arg], "-n") == 0) {
+		dry_run = true;
+		arg++;
+	}
+	if (argc - arg > 1) {
+		fprintf(stderr, "Usage: %s [-n] [L1 configuration file]\n", argv[0]);
+		return 1;
+	}
+	if (arg < argc)
+		l1_config_path = argv[arg];
+	if (dry_run)
+		return l1_check_config(l1_config_path) == 0 ? 0 : 1;
 	setup_sighandler();
 
 	struct L2 *l2 = l2_init();